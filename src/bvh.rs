@@ -0,0 +1,259 @@
+use glam::Vec3;
+
+use crate::math::{Aabb, Ray};
+
+const SAH_BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// cost of visiting a node relative to intersecting one primitive
+const TRAVERSAL_COST: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    /// index of the left child for inner nodes (the right one follows it),
+    /// index into `Bvh::indices` for leaves
+    first: u32,
+    /// number of primitives, 0 for inner nodes
+    count: u32,
+}
+
+/// Bounding volume hierarchy over anything that has a bounding box. It only
+/// stores indices, what they point to is up to the caller.
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    /// Builds the hierarchy top down, splitting with the surface area heuristic.
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(bounds.len() * 2),
+            indices: (0..bounds.len()).collect(),
+        };
+        if bounds.is_empty() {
+            return bvh;
+        }
+        let centroids: Vec<Vec3> = bounds.iter().map(|b| b.centroid()).collect();
+        bvh.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            first: 0,
+            count: bounds.len() as u32,
+        });
+        bvh.subdivide(0, bounds, &centroids);
+        bvh
+    }
+
+    fn subdivide(&mut self, node: usize, bounds: &[Aabb], centroids: &[Vec3]) {
+        let first = self.nodes[node].first as usize;
+        let count = self.nodes[node].count as usize;
+        let prims = &self.indices[first..first + count];
+
+        let node_bounds = prims.iter().fold(Aabb::EMPTY, |b, &i| b.union(bounds[i]));
+        let centroid_bounds = prims.iter().fold(Aabb::EMPTY, |b, &i| b.grow(centroids[i]));
+        self.nodes[node].bounds = node_bounds;
+
+        if count <= 1 {
+            return;
+        }
+
+        let Some((axis, split, cost)) = find_split(prims, bounds, centroids, centroid_bounds)
+        else {
+            // all centroids in the same spot, nothing to split
+            return;
+        };
+        let leaf_cost = count as f32;
+        if cost >= leaf_cost && count <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        // partition the index range around the split plane
+        let mut i = first;
+        let mut j = first + count;
+        while i < j {
+            if centroids[self.indices[i]][axis] < split {
+                i += 1;
+            } else {
+                j -= 1;
+                self.indices.swap(i, j);
+            }
+        }
+        let left_count = i - first;
+        if left_count == 0 || left_count == count {
+            return;
+        }
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            first: first as u32,
+            count: left_count as u32,
+        });
+        self.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            first: i as u32,
+            count: (count - left_count) as u32,
+        });
+        self.nodes[node].first = left as u32;
+        self.nodes[node].count = 0;
+
+        self.subdivide(left, bounds, centroids);
+        self.subdivide(left + 1, bounds, centroids);
+    }
+
+    /// Box around everything in the hierarchy, `None` if it's empty.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|n| n.bounds)
+    }
+
+    /// Walks the hierarchy front to back, calling `hit` with the index of every
+    /// primitive whose leaf the ray passes through. `hit` returns the distance
+    /// of the intersection (if any), which is used to skip farther nodes.
+    /// Returns the closest result.
+    pub fn closest<T>(
+        &self,
+        ray: Ray,
+        mut hit: impl FnMut(usize, Ray) -> Option<(f32, T)>,
+    ) -> Option<(f32, T)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut closest: Option<(f32, T)> = None;
+        let inv_dir = ray.dir.recip();
+        let mut t_max = f32::INFINITY;
+
+        let mut stack = Vec::with_capacity(64);
+        if self.nodes[0].bounds.hit(ray, inv_dir, t_max).is_some() {
+            stack.push(0);
+        }
+
+        while let Some(node) = stack.pop() {
+            let node = self.nodes[node];
+            if node.count > 0 {
+                let first = node.first as usize;
+                for &i in &self.indices[first..first + node.count as usize] {
+                    if let Some((t, value)) = hit(i, ray) {
+                        if t < t_max {
+                            t_max = t;
+                            closest = Some((t, value));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let left = node.first as usize;
+            let right = left + 1;
+            let t_left = self.nodes[left].bounds.hit(ray, inv_dir, t_max);
+            let t_right = self.nodes[right].bounds.hit(ray, inv_dir, t_max);
+            // push the farther child first so the nearer one is visited next
+            match (t_left, t_right) {
+                (Some(l), Some(r)) if l < r => stack.extend([right, left]),
+                (Some(_), Some(_)) => stack.extend([left, right]),
+                (Some(_), None) => stack.push(left),
+                (None, Some(_)) => stack.push(right),
+                (None, None) => {}
+            }
+        }
+
+        closest
+    }
+}
+
+// binned surface area heuristic, returns the axis, split position and cost of
+// the cheapest split
+fn find_split(
+    prims: &[usize],
+    bounds: &[Aabb],
+    centroids: &[Vec3],
+    centroid_bounds: Aabb,
+) -> Option<(usize, f32, f32)> {
+    let mut best: Option<(usize, f32, f32)> = None;
+
+    let axes = centroid_bounds
+        .min
+        .to_array()
+        .into_iter()
+        .zip(centroid_bounds.max.to_array());
+    for (axis, (lo, hi)) in axes.enumerate() {
+        if hi - lo <= f32::EPSILON {
+            continue;
+        }
+
+        let mut bins = [(Aabb::EMPTY, 0usize); SAH_BINS];
+        let scale = SAH_BINS as f32 / (hi - lo);
+        for &i in prims {
+            let b = (((centroids[i][axis] - lo) * scale) as usize).min(SAH_BINS - 1);
+            bins[b].0 = bins[b].0.union(bounds[i]);
+            bins[b].1 += 1;
+        }
+
+        // sweep from both sides to get the area and count left/right of each plane
+        let mut left_area = [0.0; SAH_BINS - 1];
+        let mut left_count = [0; SAH_BINS - 1];
+        let mut acc = (Aabb::EMPTY, 0);
+        for b in 0..SAH_BINS - 1 {
+            acc = (acc.0.union(bins[b].0), acc.1 + bins[b].1);
+            left_area[b] = acc.0.surface_area();
+            left_count[b] = acc.1;
+        }
+        let mut acc = (Aabb::EMPTY, 0);
+        for b in (1..SAH_BINS).rev() {
+            acc = (acc.0.union(bins[b].0), acc.1 + bins[b].1);
+            let cost =
+                left_count[b - 1] as f32 * left_area[b - 1] + acc.1 as f32 * acc.0.surface_area();
+            if best.is_none_or(|(_, _, c)| cost < c) {
+                best = Some((axis, lo + b as f32 / scale, cost));
+            }
+        }
+    }
+
+    let parent_area = prims
+        .iter()
+        .fold(Aabb::EMPTY, |b, &i| b.union(bounds[i]))
+        .surface_area()
+        .max(f32::EPSILON);
+    best.map(|(axis, split, cost)| (axis, split, TRAVERSAL_COST + cost / parent_area))
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::Bvh;
+    use crate::math::{random_vec, Aabb, Material, Ray, Renderable, Sphere};
+    use crate::rng::{self, random};
+
+    #[test]
+    fn matches_brute_force() {
+        // the same spheres and rays every run, so failures can be looked into
+        rng::seed(7);
+        let spheres: Vec<Sphere> = (0..200)
+            .map(|_| Sphere {
+                pos: random_vec(-10.0, 10.0) + Vec3::new(0.0, 0.0, 20.0),
+                rad: random::<f32>() + 0.1,
+                material: Material::default(),
+            })
+            .collect();
+        let bounds: Vec<Aabb> = spheres.iter().map(|s| s.bounds().unwrap()).collect();
+        let bvh = Bvh::build(&bounds);
+
+        for _ in 0..500 {
+            let ray = Ray {
+                pos: Vec3::ZERO,
+                dir: (random_vec(-0.5, 0.5) + Vec3::Z).normalize(),
+                ..Default::default()
+            };
+            let brute = spheres
+                .iter()
+                .filter_map(|s| s.intersect(ray))
+                .map(|h| h.t)
+                .min_by(f32::total_cmp);
+            let accel = bvh
+                .closest(ray, |i, ray| spheres[i].intersect(ray).map(|h| (h.t, ())))
+                .map(|h| h.0);
+            assert_eq!(brute, accel);
+        }
+    }
+}
@@ -0,0 +1,564 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use glam::{Mat4, Quat, UVec2, Vec2, Vec3};
+use serde::Deserialize;
+
+use crate::math::Ray;
+use crate::motion::Motion;
+use crate::rng::random;
+use crate::sampling::{regular_polygon, unit_disk, Distribution2d};
+
+/// Field of view in degrees, across the whole width or height of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Fov {
+    Horizontal(f32),
+    Vertical(f32),
+}
+
+impl Default for Fov {
+    // what the renderer used before the fov could be set, the image is one
+    // unit wide at a distance of one
+    fn default() -> Self {
+        Fov::Horizontal(2.0 * 0.5f32.atan().to_degrees())
+    }
+}
+
+/// How directions around the camera are laid out on the image.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum Projection {
+    /// like a photo, the only one that uses `fov` and the lens
+    #[default]
+    Perspective,
+    /// parallel rays, nothing gets smaller with distance. `width` is how
+    /// much of the world fits across the image
+    Orthographic { width: f32 },
+    /// a circle as big as the shorter side of the image showing `angle`
+    /// degrees around the view direction
+    Fisheye { angle: f32, mapping: FisheyeMapping },
+    /// everything around the camera, longitude across and latitude down
+    Equirectangular,
+    /// the six faces of a cube around the camera in a 3 by 2 grid, right,
+    /// left and up on top, down, front and back below
+    Cubemap,
+}
+
+/// How the distance from the center of a fisheye image grows with the angle
+/// to the view direction.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum FisheyeMapping {
+    /// evenly with the angle
+    Equidistant,
+    /// keeps areas the same size, the edge gets squeezed less
+    Equisolid,
+}
+
+/// Renders an image for each eye next to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Stereo {
+    /// distance between the eyes in world units
+    pub eye_distance: f32,
+    /// distance at which the two views line up, things closer than it seem
+    /// to come out of the screen. the eyes look parallel if not set
+    pub convergence: Option<f32>,
+    pub layout: StereoLayout,
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Self {
+            eye_distance: 0.064,
+            convergence: None,
+            layout: StereoLayout::SideBySide,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum StereoLayout {
+    /// left eye on the left half
+    SideBySide,
+    /// left eye on the top half
+    TopBottom,
+}
+
+/// A thin lens camera, or a pinhole camera without an aperture. Scenes get
+/// built in its view space, so the rays it makes start around the origin.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Camera {
+    pub pos: Vec3,
+    /// direction the camera looks in, ignored if `look_at` is set
+    pub dir: Vec3,
+    pub look_at: Option<Vec3>,
+    /// which way is up in the world, doesn't have to be perpendicular to the
+    /// view direction
+    pub up: Vec3,
+    /// turns the camera clockwise around the view direction, in degrees
+    pub roll: f32,
+    pub projection: Projection,
+    pub fov: Fov,
+    /// width over height of what the camera sees, squeezed or stretched onto
+    /// the image if it doesn't match the resolution. that of the image if
+    /// not set
+    pub aspect: Option<f32>,
+    /// radius of the lens, 0 keeps everything sharp
+    pub aperture: f32,
+    /// distance to where things are sharp, the `look_at` target if not set
+    /// and far away without one
+    pub focus_distance: Option<f32>,
+    /// number of straight blades that make up the aperture, out of focus
+    /// highlights take its shape. round with less than 3
+    pub blades: u32,
+    /// turns the blades, in degrees
+    pub blade_rotation: f32,
+    /// image of the aperture used instead of the blades, brighter parts let
+    /// more light through. loaded by `load_bokeh`
+    pub bokeh: Option<PathBuf>,
+    #[serde(skip)]
+    pub bokeh_shape: Option<Arc<Distribution2d>>,
+    /// renders both eyes into one image if set. the 360 degree projections
+    /// get omni-directional stereo, the eyes circle around the camera
+    /// position to always stand side by side across the ray
+    pub stereo: Option<Stereo>,
+    /// when the shutter opens and closes, every ray is sent at a random
+    /// time in between so things that move get blurred
+    pub shutter: (f32, f32),
+    /// moves and turns the camera over time, along and around its own axes
+    /// with x to the right, y up and z forward
+    pub motion: Motion,
+    /// size of the image in pixels, set by the renderer
+    #[serde(skip)]
+    pub resolution: UVec2,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            pos: Vec3::ZERO,
+            dir: Vec3::Z,
+            look_at: None,
+            up: Vec3::Y,
+            roll: 0.0,
+            projection: Projection::default(),
+            fov: Fov::default(),
+            aspect: None,
+            aperture: 0.0,
+            focus_distance: None,
+            blades: 0,
+            blade_rotation: 0.0,
+            bokeh: None,
+            bokeh_shape: None,
+            stereo: None,
+            shutter: (0.0, 1.0),
+            motion: Motion::default(),
+            resolution: UVec2::new(1920, 1080),
+        }
+    }
+}
+
+impl Camera {
+    pub fn forward(&self) -> Vec3 {
+        match self.look_at {
+            Some(target) => (target - self.pos).normalize(),
+            None => self.dir.normalize(),
+        }
+    }
+
+    /// Moves world space into view space, where the camera sits at the
+    /// origin looking along z with y up.
+    pub fn view_matrix(&self) -> Mat4 {
+        let forward = self.forward();
+        let up = Quat::from_axis_angle(forward, -self.roll.to_radians()) * self.up;
+        Mat4::look_to_lh(self.pos, forward, up)
+    }
+
+    pub fn focus_distance(&self) -> f32 {
+        match (self.focus_distance, self.look_at) {
+            (Some(distance), _) => distance,
+            (None, Some(target)) => self.pos.distance(target),
+            (None, None) => f32::INFINITY,
+        }
+    }
+
+    /// Reads the `bokeh` image if there is one, relative paths start at
+    /// `dir`. An image that is black all over leaves `bokeh_shape` empty.
+    pub fn load_bokeh(&mut self, dir: &Path) -> Result<(), image::ImageError> {
+        let Some(path) = &self.bokeh else {
+            return Ok(());
+        };
+        let image = image::open(dir.join(path))?.to_luma32f();
+        let weights: Vec<f32> = image.pixels().map(|p| p.0[0]).collect();
+        self.bokeh_shape = Distribution2d::new(&weights, image.width() as usize).map(Arc::new);
+        Ok(())
+    }
+
+    /// A random time while the shutter is open.
+    pub fn sample_time(&self) -> f32 {
+        let (open, close) = self.shutter;
+        open + (close - open) * random::<f32>()
+    }
+
+    /// Ray through `pixel`, counted from the top left corner, in view space.
+    /// `sample` is where in the pixel it goes through, from 0 to 1 on both
+    /// axes. `None` where the projection doesn't cover the image.
+    pub fn generate_ray(&self, pixel: UVec2, sample: Vec2) -> Option<Ray> {
+        let ray = self.still_ray(pixel, sample)?;
+        let time = self.sample_time();
+        let motion = self.motion.at(time);
+        Some(Ray {
+            pos: motion.transform_point3(ray.pos),
+            dir: motion.transform_vector3(ray.dir),
+            time,
+        })
+    }
+
+    // the ray before the camera moves
+    fn still_ray(&self, pixel: UVec2, sample: Vec2) -> Option<Ray> {
+        let film = (pixel.as_vec2() + sample) / self.resolution.as_vec2();
+        let Some(stereo) = self.stereo else {
+            return self.project(film, self.aspect(self.resolution.as_vec2()));
+        };
+
+        // which eye the pixel belongs to and where it is in the eye's image
+        let (side, film, eye_size) = match stereo.layout {
+            StereoLayout::SideBySide => {
+                let side = if film.x < 0.5 { -1.0 } else { 1.0 };
+                let x = (film.x * 2.0).fract();
+                (side, Vec2::new(x, film.y), Vec2::new(0.5, 1.0))
+            }
+            StereoLayout::TopBottom => {
+                let side = if film.y < 0.5 { -1.0 } else { 1.0 };
+                let y = (film.y * 2.0).fract();
+                (side, Vec2::new(film.x, y), Vec2::new(1.0, 0.5))
+            }
+        };
+        let ray = self.project(film, self.aspect(self.resolution.as_vec2() * eye_size))?;
+
+        let right = match self.projection {
+            Projection::Equirectangular | Projection::Cubemap => {
+                // across the ray, in the horizontal plane
+                let azimuth = ray.dir.x.atan2(ray.dir.z);
+                Vec3::new(azimuth.cos(), 0.0, -azimuth.sin())
+            }
+            _ => Vec3::X,
+        };
+        let eye = right * (side * stereo.eye_distance / 2.0);
+        // the ray still reaches the same point at the convergence distance
+        let convergence = stereo.convergence.unwrap_or(f32::INFINITY);
+        Some(Ray {
+            pos: ray.pos + eye,
+            dir: ray.dir - eye / convergence,
+            ..ray
+        })
+    }
+
+    // ray through `film`, which goes from 0 to 1 across the image
+    fn project(&self, film: Vec2, aspect: f32) -> Option<Ray> {
+        // -1 to 1 from left to right and bottom to top
+        let ndc = Vec2::new(2.0 * film.x - 1.0, 1.0 - 2.0 * film.y);
+        let dir = |dir| {
+            Some(Ray {
+                pos: Vec3::ZERO,
+                dir,
+                ..Default::default()
+            })
+        };
+        match self.projection {
+            Projection::Perspective => Some(self.perspective_ray(ndc, aspect)),
+            Projection::Orthographic { width } => {
+                let half = Vec2::new(1.0, 1.0 / aspect) * width / 2.0;
+                Some(Ray {
+                    pos: (ndc * half).extend(0.0),
+                    dir: Vec3::Z,
+                    ..Default::default()
+                })
+            }
+            Projection::Fisheye { angle, mapping } => {
+                let p = if aspect >= 1.0 {
+                    Vec2::new(ndc.x * aspect, ndc.y)
+                } else {
+                    Vec2::new(ndc.x, ndc.y / aspect)
+                };
+                let r = p.length();
+                if r > 1.0 {
+                    return None;
+                }
+                let max = angle.to_radians() / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * max,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (max / 2.0).sin()).asin(),
+                };
+                let phi = p.y.atan2(p.x);
+                dir(Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ))
+            }
+            Projection::Equirectangular => {
+                let (lon, lat) = (ndc.x * PI, ndc.y * FRAC_PI_2);
+                dir(Vec3::new(
+                    lat.cos() * lon.sin(),
+                    lat.sin(),
+                    lat.cos() * lon.cos(),
+                ))
+            }
+            Projection::Cubemap => {
+                let cell = film * Vec2::new(3.0, 2.0);
+                let (col, row) = ((cell.x as usize).min(2), (cell.y as usize).min(1));
+                // -1 to 1 across the face, up is positive
+                let u = 2.0 * (cell.x - col as f32) - 1.0;
+                let v = 1.0 - 2.0 * (cell.y - row as f32);
+                // right, up and forward of each face
+                let (right, up, forward) = match row * 3 + col {
+                    0 => (-Vec3::Z, Vec3::Y, Vec3::X),
+                    1 => (Vec3::Z, Vec3::Y, -Vec3::X),
+                    2 => (Vec3::X, -Vec3::Z, Vec3::Y),
+                    3 => (Vec3::X, Vec3::Z, -Vec3::Y),
+                    4 => (Vec3::X, Vec3::Y, Vec3::Z),
+                    _ => (-Vec3::X, Vec3::Y, -Vec3::Z),
+                };
+                dir(right * u + up * v + forward)
+            }
+        }
+    }
+
+    fn perspective_ray(&self, ndc: Vec2, aspect: f32) -> Ray {
+        let dir = (ndc * self.half_extent(aspect)).extend(1.0);
+        if self.aperture <= 0.0 {
+            return Ray {
+                pos: Vec3::ZERO,
+                dir,
+                ..Default::default()
+            };
+        }
+
+        // start somewhere on the lens and go through the point the pinhole
+        // ray reaches at the focus distance, where `dir` is one deep
+        let lens = (self.sample_aperture() * self.aperture).extend(0.0);
+        Ray {
+            pos: lens,
+            dir: dir - lens / self.focus_distance(),
+            ..Default::default()
+        }
+    }
+
+    // point on the aperture, the lens is the unit circle
+    fn sample_aperture(&self) -> Vec2 {
+        if let Some(shape) = &self.bokeh_shape {
+            // the image spans the lens along its longer side, it counts
+            // rows down
+            let size = Vec2::new(shape.width() as f32, shape.height() as f32);
+            let scale = size / size.max_element();
+            let p = shape.sample() * 2.0 - Vec2::ONE;
+            Vec2::new(p.x, -p.y) * scale
+        } else if self.blades >= 3 {
+            regular_polygon(self.blades, self.blade_rotation.to_radians())
+        } else {
+            unit_disk().0
+        }
+    }
+
+    // `size` is how big the image is that the view goes onto
+    fn aspect(&self, size: Vec2) -> f32 {
+        self.aspect.unwrap_or(size.x / size.y)
+    }
+
+    // half the width and height of the image plane at a distance of one
+    fn half_extent(&self, aspect: f32) -> Vec2 {
+        match self.fov {
+            Fov::Horizontal(deg) => {
+                let w = (deg.to_radians() / 2.0).tan();
+                Vec2::new(w, w / aspect)
+            }
+            Fov::Vertical(deg) => {
+                let h = (deg.to_radians() / 2.0).tan();
+                Vec2::new(h * aspect, h)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{UVec2, Vec2, Vec3};
+
+    use super::{Camera, FisheyeMapping, Fov, Projection, Stereo, StereoLayout};
+    use crate::motion::{Keyframe, Motion};
+
+    #[test]
+    fn corners_follow_the_fov() {
+        let camera = Camera {
+            fov: Fov::Vertical(90.0),
+            resolution: UVec2::new(200, 100),
+            ..Default::default()
+        };
+        let top_left = camera.generate_ray(UVec2::ZERO, Vec2::ZERO).unwrap().dir;
+        assert!(top_left.abs_diff_eq(Vec3::new(-2.0, 1.0, 1.0), 1e-5));
+        let bottom_right = camera
+            .generate_ray(UVec2::new(199, 99), Vec2::ONE)
+            .unwrap()
+            .dir;
+        assert!(bottom_right.abs_diff_eq(Vec3::new(2.0, -1.0, 1.0), 1e-5));
+
+        let squeezed = Camera {
+            aspect: Some(1.0),
+            ..camera
+        };
+        let top_left = squeezed.generate_ray(UVec2::ZERO, Vec2::ZERO).unwrap().dir;
+        assert!(top_left.abs_diff_eq(Vec3::new(-1.0, 1.0, 1.0), 1e-5));
+    }
+
+    #[test]
+    fn looks_at_the_target() {
+        let camera = Camera {
+            pos: Vec3::new(1.0, 2.0, 3.0),
+            look_at: Some(Vec3::new(4.0, 2.0, -1.0)),
+            ..Default::default()
+        };
+        let target = camera
+            .view_matrix()
+            .transform_point3(Vec3::new(4.0, 2.0, -1.0));
+        assert!(target.abs_diff_eq(Vec3::new(0.0, 0.0, 5.0), 1e-5));
+    }
+
+    #[test]
+    fn lens_rays_meet_at_the_focus_distance() {
+        let pinhole = Camera {
+            focus_distance: Some(4.0),
+            ..Default::default()
+        };
+        let lens = Camera {
+            aperture: 0.5,
+            blades: 6,
+            ..pinhole.clone()
+        };
+        let (pixel, sample) = (UVec2::new(300, 700), Vec2::new(0.2, 0.9));
+        let sharp = pinhole.generate_ray(pixel, sample).unwrap().dir * 4.0;
+        for _ in 0..100 {
+            let ray = lens.generate_ray(pixel, sample).unwrap();
+            assert!(ray.pos.length() <= 0.5 + 1e-5);
+            assert!((ray.pos + ray.dir * 4.0).abs_diff_eq(sharp, 1e-4));
+        }
+    }
+
+    #[test]
+    fn roll_turns_the_view() {
+        // rolled a quarter turn clockwise, what is right of the camera in the
+        // world is up in the image
+        let camera = Camera {
+            roll: 90.0,
+            ..Default::default()
+        };
+        let right = camera
+            .view_matrix()
+            .transform_point3(Vec3::new(1.0, 0.0, 1.0));
+        assert!(right.abs_diff_eq(Vec3::new(0.0, 1.0, 1.0), 1e-5));
+    }
+
+    #[test]
+    fn projections_look_the_right_way() {
+        let ray = |projection, pixel: UVec2| {
+            let camera = Camera {
+                projection,
+                resolution: UVec2::new(300, 200),
+                ..Default::default()
+            };
+            camera
+                .generate_ray(pixel, Vec2::splat(0.5))
+                .map(|ray| (ray.pos, ray.dir.normalize()))
+        };
+        let center = UVec2::new(150, 100);
+
+        let ortho = Projection::Orthographic { width: 6.0 };
+        let (pos, dir) = ray(ortho, UVec2::ZERO).unwrap();
+        assert!(pos.abs_diff_eq(Vec3::new(-2.99, 1.99, 0.0), 1e-4));
+        assert_eq!(dir, Vec3::Z);
+
+        let fisheye = Projection::Fisheye {
+            angle: 180.0,
+            mapping: FisheyeMapping::Equisolid,
+        };
+        assert!(ray(fisheye, UVec2::ZERO).is_none());
+        let (_, dir) = ray(fisheye, center).unwrap();
+        assert!(dir.abs_diff_eq(Vec3::Z, 1e-2));
+        // the edge of the circle is at a right angle to the view
+        let (_, dir) = ray(fisheye, UVec2::new(150, 0)).unwrap();
+        assert!(dir.z.abs() < 0.02 && dir.y > 0.99);
+
+        let (_, dir) = ray(Projection::Equirectangular, center).unwrap();
+        assert!(dir.abs_diff_eq(Vec3::Z, 2e-2));
+        let (_, dir) = ray(Projection::Equirectangular, UVec2::new(0, 100)).unwrap();
+        assert!(dir.abs_diff_eq(-Vec3::Z, 2e-2));
+
+        // face centers of the 3 by 2 grid
+        let faces = [Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y, Vec3::Z, -Vec3::Z];
+        for (i, face) in faces.into_iter().enumerate() {
+            let pixel = UVec2::new(50 + 100 * (i as u32 % 3), 50 + 100 * (i as u32 / 3));
+            let (_, dir) = ray(Projection::Cubemap, pixel).unwrap();
+            assert!(dir.abs_diff_eq(face, 1e-2), "{i}: {dir}");
+        }
+    }
+
+    #[test]
+    fn stereo_eyes_converge() {
+        let camera = Camera {
+            stereo: Some(Stereo {
+                eye_distance: 0.1,
+                convergence: Some(3.0),
+                layout: StereoLayout::TopBottom,
+            }),
+            resolution: UVec2::new(200, 200),
+            ..Default::default()
+        };
+        // the same spot in both halves
+        let sample = Vec2::new(0.3, 0.6);
+        let left = camera.generate_ray(UVec2::new(40, 20), sample).unwrap();
+        let right = camera.generate_ray(UVec2::new(40, 120), sample).unwrap();
+        assert!(left.pos.abs_diff_eq(Vec3::new(-0.05, 0.0, 0.0), 1e-5));
+        assert!(right.pos.abs_diff_eq(Vec3::new(0.05, 0.0, 0.0), 1e-5));
+        let (l, r) = (left.pos + left.dir * 3.0, right.pos + right.dir * 3.0);
+        assert!(l.abs_diff_eq(r, 1e-4));
+
+        // omni-directional, the eyes stand across every ray
+        let ods = Camera {
+            projection: Projection::Equirectangular,
+            stereo: Some(Stereo::default()),
+            ..camera
+        };
+        for x in (0..200).step_by(7) {
+            let ray = ods.generate_ray(UVec2::new(x, 60), sample).unwrap();
+            assert!((ray.pos.length() - 0.032).abs() < 1e-5);
+            assert!(ray.pos.dot(ray.dir).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn moves_while_the_shutter_is_open() {
+        let camera = Camera {
+            shutter: (0.5, 1.0),
+            motion: Motion::new(vec![
+                Keyframe::default(),
+                Keyframe {
+                    time: 1.0,
+                    translate: Vec3::new(2.0, 0.0, 0.0),
+                    rotate: Vec3::new(0.0, 90.0, 0.0),
+                    ..Default::default()
+                },
+            ]),
+            resolution: UVec2::new(1, 1),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let ray = camera.generate_ray(UVec2::ZERO, Vec2::splat(0.5)).unwrap();
+            assert!((0.5..=1.0).contains(&ray.time));
+            assert!((ray.pos.x - 2.0 * ray.time).abs() < 1e-5);
+            // turned towards x
+            let angle = ray.dir.x.atan2(ray.dir.z).to_degrees();
+            assert!((angle - 90.0 * ray.time).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use image::ImageFormat;
+
+use term_rend_rt::{IntegratorKind, RenderSettings};
+
+const PREVIEW_SAMPLES: u32 = 4;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "A path tracer that can render into the terminal")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Render the scene at full quality and save it
+    Render(RenderArgs),
+    /// Render a quick low sample preview and show it
+    Preview(RenderArgs),
+    /// Print what is in the scene file without rendering it
+    Info {
+        /// Path of the .ron scene file
+        scene: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DisplayMode {
+    /// Window if there is a display server to open one on, terminal otherwise
+    Auto,
+    Window,
+    Terminal,
+    /// Don't show the image, only save it
+    Headless,
+}
+
+impl DisplayMode {
+    pub fn resolve(self) -> Self {
+        if self != DisplayMode::Auto {
+            return self;
+        }
+        let has_display = cfg!(any(target_os = "windows", target_os = "macos"))
+            || std::env::var_os("DISPLAY").is_some()
+            || std::env::var_os("WAYLAND_DISPLAY").is_some();
+        if has_display {
+            DisplayMode::Window
+        } else {
+            DisplayMode::Terminal
+        }
+    }
+}
+
+/// Flags shared by `render` and `preview`, anything given here overrides the
+/// scene file.
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    /// Path of the .ron scene file
+    pub scene: PathBuf,
+    /// Image size as WIDTHxHEIGHT
+    #[arg(short, long, value_parser = parse_resolution)]
+    pub resolution: Option<(u32, u32)>,
+    /// Samples per pixel
+    #[arg(short, long)]
+    pub spp: Option<u32>,
+    /// Maximum number of bounces per path, replaces the limits per kind of
+    /// bounce as well
+    #[arg(short = 'd', long)]
+    pub max_depth: Option<u32>,
+    /// Where to save the image, `render` defaults to rendered_image.png
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Image format, guessed from the output file extension if not given
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<ImageFormat>,
+    /// Number of render threads, defaults to one per core
+    #[arg(short = 'j', long)]
+    pub threads: Option<usize>,
+    /// Seed for the random numbers, makes renders reproducible
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Integrator to render with: Path, Bdpt, Photon, Mlt, Whitted,
+    /// AmbientOcclusion, Direct, Normals, Depth, Uv or MaterialId
+    #[arg(short, long, value_parser = parse_integrator)]
+    pub integrator: Option<IntegratorKind>,
+    /// How to show the finished image, `render` defaults to headless and
+    /// `preview` to auto
+    #[arg(long, value_enum)]
+    pub display: Option<DisplayMode>,
+}
+
+impl RenderArgs {
+    pub fn apply(&self, settings: &mut RenderSettings) {
+        if let Some((width, height)) = self.resolution {
+            settings.width = width;
+            settings.height = height;
+        }
+        if let Some(spp) = self.spp {
+            settings.samples_per_pixel = spp;
+        }
+        if let Some(depth) = self.max_depth {
+            settings.max_bounces = depth;
+            settings.max_diffuse_bounces = depth;
+            settings.max_specular_bounces = depth;
+            settings.max_transmission_bounces = depth;
+        }
+        if self.seed.is_some() {
+            settings.seed = self.seed;
+        }
+        if let Some(integrator) = self.integrator {
+            settings.integrator = integrator;
+        }
+    }
+
+    /// Previews render with few samples unless asked for more.
+    pub fn apply_preview(&self, settings: &mut RenderSettings) {
+        settings.samples_per_pixel = settings.samples_per_pixel.min(PREVIEW_SAMPLES);
+        self.apply(settings);
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    let (w, h) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got \"{s}\""))?;
+    let parse = |v: &str| match v.trim().parse::<u32>() {
+        Ok(0) => Err("resolution can't be 0".to_owned()),
+        Ok(v) => Ok(v),
+        Err(e) => Err(format!("invalid number \"{v}\": {e}")),
+    };
+    Ok((parse(w)?, parse(h)?))
+}
+
+// same names as in scene files
+fn parse_integrator(s: &str) -> Result<IntegratorKind, String> {
+    ron::from_str(s).map_err(|_| format!("unknown integrator \"{s}\""))
+}
+
+fn parse_format(s: &str) -> Result<ImageFormat, String> {
+    ImageFormat::from_extension(s).ok_or_else(|| format!("unknown image format \"{s}\""))
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::{parse_integrator, parse_resolution, Cli, Command};
+    use term_rend_rt::{IntegratorKind, RenderSettings};
+
+    #[test]
+    fn resolution() {
+        assert_eq!(parse_resolution("640x480"), Ok((640, 480)));
+        assert!(parse_resolution("640").is_err());
+        assert!(parse_resolution("0x480").is_err());
+    }
+
+    #[test]
+    fn integrator_names() {
+        assert_eq!(
+            parse_integrator("AmbientOcclusion"),
+            Ok(IntegratorKind::AmbientOcclusion)
+        );
+        assert!(parse_integrator("Raymarch").is_err());
+    }
+
+    #[test]
+    fn flags_override_scene() {
+        let cli = Cli::parse_from(["trt", "render", "a.ron", "-r", "64x32", "--spp", "8"]);
+        let Command::Render(args) = cli.command else {
+            panic!("expected the render command");
+        };
+        let mut settings = RenderSettings::default();
+        args.apply(&mut settings);
+        assert_eq!((settings.width, settings.height), (64, 32));
+        assert_eq!(settings.samples_per_pixel, 8);
+        assert_eq!(settings.max_bounces, RenderSettings::default().max_bounces);
+
+        let cli = Cli::parse_from(["trt", "render", "a.ron", "-d", "20"]);
+        let Command::Render(args) = cli.command else {
+            panic!("expected the render command");
+        };
+        args.apply(&mut settings);
+        assert_eq!(settings.max_bounces, 20);
+        assert_eq!(settings.max_diffuse_bounces, 20);
+        assert_eq!(settings.max_specular_bounces, 20);
+    }
+}
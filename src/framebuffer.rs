@@ -0,0 +1,53 @@
+use image::{Rgb, RgbImage};
+
+use crate::math::Color;
+
+/// Linear, unclamped pixel colors as they come out of the renderer.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::BLACK; (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    /// Converts to 8 bit with a gamma of 2, values above 1 are clipped.
+    pub fn to_rgb_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let c = self.get(x, y);
+            Rgb([
+                (255.0 * c.r.sqrt()) as u8,
+                (255.0 * c.g.sqrt()) as u8,
+                (255.0 * c.b.sqrt()) as u8,
+            ])
+        })
+    }
+}
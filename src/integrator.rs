@@ -0,0 +1,171 @@
+use glam::Vec3;
+use serde::Deserialize;
+
+use crate::camera::Camera;
+use crate::light::{area_pdf, power_heuristic};
+use crate::math::{Color, Hit, Ray};
+use crate::rng::random;
+use crate::sampling::cosine_hemisphere_pdf;
+use crate::scene::Scene;
+use crate::settings::RenderSettings;
+
+mod ao;
+mod bdpt;
+mod debug;
+mod direct;
+mod mlt;
+mod path;
+mod photon;
+mod whitted;
+
+pub use ao::AmbientOcclusion;
+pub use bdpt::Bdpt;
+pub use debug::DebugView;
+pub use direct::DirectLighting;
+pub use mlt::Metropolis;
+pub use path::PathTracer;
+pub use photon::PhotonMapper;
+pub use whitted::Whitted;
+
+/// Computes how much light arrives along camera rays.
+pub trait Integrator: Send + Sync {
+    fn li(&self, scene: &Scene, ray: Ray) -> Color;
+
+    /// How many times the image gets rendered, the samples are split between
+    /// the passes and the results averaged. There are never more passes than
+    /// samples per pixel.
+    fn passes(&self) -> u32 {
+        1
+    }
+
+    /// Called before every pass, for work shared by all pixels.
+    fn prepare(&mut self, _scene: &Scene, _pass: u32) {}
+
+    /// Renders a whole pass, for integrators that decide themselves which
+    /// pixels their samples land in. `li` is only used when this returns
+    /// `None`.
+    fn render_image(&self, _scene: &Scene, _film: &Film) -> Option<Vec<Color>> {
+        None
+    }
+}
+
+/// The image `Integrator::render_image` renders.
+pub struct Film<'a> {
+    /// with the resolution of the image
+    pub camera: &'a Camera,
+    /// samples per pixel in this pass
+    pub samples: u32,
+    /// to be called with how much of the pass is done, from 0 to 1
+    pub progress: &'a (dyn Fn(f32) + Sync),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum IntegratorKind {
+    /// full global illumination, the default
+    Path,
+    /// bidirectional path tracing, for scenes where the light is hard to
+    /// find from the camera
+    Bdpt,
+    /// path tracing with caustics from progressive photon mapping
+    Photon,
+    /// metropolis light transport, mutates paths that found light to find
+    /// more like them. for light that only gets through narrow gaps
+    Mlt,
+    /// perfect reflections and refractions plus direct light, no indirect
+    Whitted,
+    /// how much of the hemisphere above each point is open, within
+    /// `ao_distance`
+    AmbientOcclusion,
+    /// light from the light sources hitting the first surface, no bounces
+    Direct,
+    Normals,
+    Depth,
+    Uv,
+    MaterialId,
+}
+
+impl IntegratorKind {
+    pub fn build(self, settings: &RenderSettings) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::Path => Box::new(PathTracer::new(settings)),
+            IntegratorKind::Bdpt => Box::new(Bdpt {
+                max_depth: settings.max_bounces.min(settings.max_diffuse_bounces),
+            }),
+            IntegratorKind::Photon => Box::new(PhotonMapper::new(settings)),
+            IntegratorKind::Mlt => Box::new(Metropolis::new(settings)),
+            IntegratorKind::Whitted => Box::new(Whitted {
+                max_depth: settings.whitted_depth.min(settings.max_bounces),
+            }),
+            IntegratorKind::AmbientOcclusion => Box::new(AmbientOcclusion {
+                distance: settings.ao_distance,
+            }),
+            IntegratorKind::Direct => Box::new(DirectLighting),
+            IntegratorKind::Normals => Box::new(DebugView::Normals),
+            IntegratorKind::Depth => Box::new(DebugView::Depth),
+            IntegratorKind::Uv => Box::new(DebugView::Uv),
+            IntegratorKind::MaterialId => Box::new(DebugView::MaterialId),
+        }
+    }
+}
+
+/// Where a ray hit a surface, with the normal turned towards the ray.
+#[derive(Debug, Clone, Copy)]
+struct Surface {
+    p: Vec3,
+    n: Vec3,
+    /// whether the ray hit the outside of the object
+    front_face: bool,
+}
+
+impl Surface {
+    // `ray` has to be normalized
+    fn new(ray: Ray, hit: &Hit) -> Self {
+        let n = hit.normal.normalize();
+        // objects report outward normals, so this tells whether the ray is
+        // entering or leaving
+        let front_face = n.dot(ray.dir) < 0.0;
+        Self {
+            p: ray.pos + ray.dir * hit.t,
+            // always shade the side the ray came from
+            n: if front_face { n } else { -n },
+            front_face,
+        }
+    }
+}
+
+// light arriving at `p` straight from one randomly picked light source, with
+// shadows at `time` and weighed against finding the light with a cosine
+// weighted bounce
+fn sample_lights(scene: &Scene, p: Vec3, n: Vec3, time: f32) -> Color {
+    if scene.lights.is_empty() {
+        return Color::BLACK;
+    }
+    let count = scene.lights.len();
+    let light = &scene.lights[(random::<f32>() * count as f32) as usize % count];
+    let Some(sample) = light.sample(p) else {
+        return Color::BLACK;
+    };
+    let cos = sample.dir.dot(n);
+    if cos <= 0.0 || sample.pdf <= 0.0 || !scene.visible(p, sample.dir, sample.dist, time) {
+        return Color::BLACK;
+    }
+    let light_pdf = sample.pdf / count as f32;
+    let weight = if sample.delta {
+        1.0
+    } else {
+        power_heuristic(light_pdf, cosine_hemisphere_pdf(cos))
+    };
+    sample.li * (cos * weight / light_pdf)
+}
+
+// emission at a hit found by a cosine weighted bounce with density
+// `bsdf_pdf`, weighed against `sample_lights` having found it
+fn weighted_emission(scene: &Scene, ray: Ray, hit: &Hit, bsdf_pdf: f32) -> Color {
+    let emission = hit.material.emission;
+    if emission.is_black() {
+        return emission;
+    }
+    let cos_l = hit.normal.normalize().dot(ray.dir).abs();
+    let light_pdf = area_pdf(hit.t, cos_l, hit.area) / scene.lights.len().max(1) as f32;
+    emission * power_heuristic(bsdf_pdf, light_pdf)
+}
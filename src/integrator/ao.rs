@@ -0,0 +1,68 @@
+use super::{Integrator, Surface};
+use crate::math::{Color, Ray};
+use crate::sampling::cosine_hemisphere;
+use crate::scene::Scene;
+
+/// White where a random cosine weighted direction from the first hit gets
+/// `distance` away without hitting anything, black otherwise. Averaging the
+/// samples gives how open the hemisphere above each point is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmbientOcclusion {
+    pub distance: f32,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn li(&self, scene: &Scene, mut ray: Ray) -> Color {
+        ray.normalize();
+        let Some(hit) = scene.find_closest(ray) else {
+            return Color::WHITE;
+        };
+        let surface = Surface::new(ray, &hit);
+        let (dir, _) = cosine_hemisphere(surface.n);
+        if scene.visible(surface.p, dir, self.distance, ray.time) {
+            Color::WHITE
+        } else {
+            Color::BLACK
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::AmbientOcclusion;
+    use crate::camera::Camera;
+    use crate::integrator::Integrator;
+    use crate::math::{Color, Material, Plane, Ray, Sphere};
+    use crate::scene::SceneBuilder;
+
+    #[test]
+    fn open_sky_and_closed_room() {
+        let camera = Camera::default();
+        let down = Ray {
+            pos: Vec3::ZERO,
+            dir: -Vec3::Y,
+            ..Default::default()
+        };
+        let ao = AmbientOcclusion { distance: 10.0 };
+
+        let floor = SceneBuilder::new()
+            .object(Plane {
+                pos: -Vec3::Y,
+                norm: Vec3::Y,
+                material: Material::default(),
+            })
+            .build(&camera);
+        assert!((0..100).all(|_| ao.li(&floor, down) == Color::WHITE));
+
+        let room = SceneBuilder::new()
+            .object(Sphere {
+                pos: Vec3::ZERO,
+                rad: 2.0,
+                material: Material::default(),
+            })
+            .build(&camera);
+        assert!((0..100).all(|_| ao.li(&room, down) == Color::BLACK));
+    }
+}
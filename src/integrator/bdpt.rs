@@ -0,0 +1,521 @@
+use std::f32::consts::FRAC_1_PI;
+
+use glam::Vec3;
+
+use super::{Integrator, Surface};
+use crate::light::Light;
+use crate::math::{fresnel_dielectric, random_vec_in_unit_sphere, Color, Ray, MIN_HIT_DIST};
+use crate::rng::random;
+use crate::sampling::{cosine_hemisphere, cosine_hemisphere_pdf};
+use crate::scene::Scene;
+
+/// Bidirectional path tracer. Every camera ray gets a path from the camera
+/// and one from a random light, and every vertex of the one is connected to
+/// every vertex of the other. Each way of building a path is weighed with
+/// the power heuristic, so light that is easy to reach from the lights (small
+/// openings, hidden lamps) converges much faster than with the path tracer.
+///
+/// Light paths are never connected straight to the camera since that would
+/// need to splat into other pixels, the other strategies cover those paths.
+/// The sun can't start light paths, it's only sampled from the camera side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bdpt {
+    /// most bounces a full path can have
+    pub max_depth: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Camera,
+    /// start of a light path
+    Light(Light),
+    Surface {
+        color: Color,
+        emission: Color,
+        area: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+    kind: Kind,
+    p: Vec3,
+    /// `None` for the camera and point lights
+    n: Option<Vec3>,
+    /// what the path up to here carries, divided by its pdf
+    beta: Color,
+    /// scattered like a mirror or glass, other paths can't connect to it
+    delta: bool,
+    /// area density of sampling the vertex coming from the camera (`fwd` for
+    /// camera paths) or from the light
+    pdf_fwd: f32,
+    pdf_rev: f32,
+}
+
+impl Vertex {
+    // turns a density per solid angle at this vertex into one per area at `next`
+    fn convert(&self, pdf_dir: f32, next: &Vertex) -> f32 {
+        let d = next.p - self.p;
+        let dist_sq = d.length_squared();
+        if dist_sq == 0.0 {
+            return 0.0;
+        }
+        let cos = next.n.map_or(1.0, |n| n.dot(d / dist_sq.sqrt()).abs());
+        pdf_dir * cos / dist_sq
+    }
+
+    // light scattered towards `prev` when it comes from `next`, or emitted
+    // towards `next` for the start of a light path
+    fn f(&self, prev: Option<Vec3>, next: Vec3) -> Color {
+        match self.kind {
+            Kind::Camera => Color::BLACK,
+            Kind::Light(light) => light.emitted(self.n, (next - self.p).normalize()),
+            Kind::Surface { color, .. } => match (self.n, prev) {
+                (Some(n), Some(prev)) if same_side(n, prev - self.p, next - self.p) => {
+                    color * FRAC_1_PI
+                }
+                _ => Color::BLACK,
+            },
+        }
+    }
+
+    // area density at `next` of continuing the path there, coming from `prev`
+    fn pdf(&self, prev: Option<Vec3>, next: &Vertex) -> f32 {
+        match self.kind {
+            Kind::Camera => 0.0,
+            Kind::Light(_) => self.pdf_light(next),
+            Kind::Surface { .. } => match (self.n, prev) {
+                (Some(n), Some(prev)) if same_side(n, prev - self.p, next.p - self.p) => {
+                    let wi = (next.p - self.p).normalize();
+                    self.convert(cosine_hemisphere_pdf(n.dot(wi).abs()), next)
+                }
+                _ => 0.0,
+            },
+        }
+    }
+
+    // area density at `next` of a light path starting here going there
+    fn pdf_light(&self, next: &Vertex) -> f32 {
+        let dir = (next.p - self.p).normalize();
+        let pdf_dir = match self.kind {
+            Kind::Camera => 0.0,
+            Kind::Light(light) => light.emission_pdf(self.n, dir),
+            Kind::Surface { .. } => self
+                .n
+                .map_or(0.0, |n| 0.5 * cosine_hemisphere_pdf(n.dot(dir).abs())),
+        };
+        self.convert(pdf_dir, next)
+    }
+
+    // area density of a light path starting at this vertex
+    fn pdf_light_origin(&self, light_count: usize) -> f32 {
+        match self.kind {
+            Kind::Camera => 0.0,
+            Kind::Light(_) => self.pdf_fwd,
+            Kind::Surface { area, .. } => 1.0 / (area * light_count as f32),
+        }
+    }
+}
+
+fn same_side(n: Vec3, a: Vec3, b: Vec3) -> bool {
+    n.dot(a) * n.dot(b) > 0.0
+}
+
+fn remap0(pdf: f32) -> f32 {
+    if pdf != 0.0 {
+        pdf
+    } else {
+        1.0
+    }
+}
+
+impl Integrator for Bdpt {
+    fn li(&self, scene: &Scene, ray: Ray) -> Color {
+        let light_count = scene
+            .lights
+            .iter()
+            .filter(|l| !matches!(l, Light::Sun { .. }))
+            .count();
+
+        let mut camera = Vec::with_capacity(self.max_depth as usize + 1);
+        camera.push(Vertex {
+            kind: Kind::Camera,
+            p: ray.pos,
+            n: None,
+            beta: Color::WHITE,
+            delta: false,
+            pdf_fwd: 1.0,
+            pdf_rev: 0.0,
+        });
+        // the sky is only found by escaping, so nothing to weigh it against
+        let mut radiance = random_walk(scene, ray, Color::WHITE, 1.0, self.max_depth, &mut camera);
+        // both paths are traced through the scene as it is at the same time
+        let light = self.light_path(scene, light_count, ray.time);
+
+        for t in 2..=camera.len() {
+            radiance = radiance + sample_suns(scene, &camera[t - 1], ray.time);
+            for s in 0..=light.len() {
+                if s + t - 2 > self.max_depth as usize {
+                    break;
+                }
+                radiance = radiance + connect(scene, &light, &camera, s, t, light_count, ray.time);
+            }
+        }
+        radiance
+    }
+}
+
+impl Bdpt {
+    fn light_path(&self, scene: &Scene, light_count: usize, time: f32) -> Vec<Vertex> {
+        let mut path = Vec::with_capacity(self.max_depth as usize);
+        if light_count == 0 || self.max_depth == 0 {
+            return path;
+        }
+        let pick = (random::<f32>() * light_count as f32) as usize % light_count;
+        let light = scene
+            .lights
+            .iter()
+            .filter(|l| !matches!(l, Light::Sun { .. }))
+            .nth(pick)
+            .copied()
+            .unwrap();
+        let Some(sample) = light.sample_emission() else {
+            return path;
+        };
+        let pdf_pos = sample.pdf_pos / light_count as f32;
+        if pdf_pos <= 0.0 || sample.pdf_dir <= 0.0 {
+            return path;
+        }
+        path.push(Vertex {
+            kind: Kind::Light(light),
+            p: sample.pos,
+            n: sample.normal,
+            beta: Color::WHITE * (1.0 / pdf_pos),
+            delta: false,
+            pdf_fwd: pdf_pos,
+            pdf_rev: 0.0,
+        });
+
+        let cos = sample.normal.map_or(1.0, |n| n.dot(sample.dir).abs());
+        let beta = light.emitted(sample.normal, sample.dir) * (cos / (pdf_pos * sample.pdf_dir));
+        let ray = Ray {
+            pos: sample.pos,
+            dir: sample.dir,
+            time,
+        };
+        random_walk(
+            scene,
+            ray,
+            beta,
+            sample.pdf_dir,
+            self.max_depth - 1,
+            &mut path,
+        );
+        path
+    }
+}
+
+// extends `path` by up to `max_depth` vertices, scattering like the path
+// tracer does. Returns the sky seen if the path escapes
+fn random_walk(
+    scene: &Scene,
+    mut ray: Ray,
+    mut beta: Color,
+    mut pdf_dir: f32,
+    max_depth: u32,
+    path: &mut Vec<Vertex>,
+) -> Color {
+    for bounce in 0..max_depth {
+        ray.normalize();
+        let Some(hit) = scene.find_closest(ray) else {
+            return scene.sky(ray.dir) * beta;
+        };
+        let mat = hit.material;
+        let surface = Surface::new(ray, &hit);
+        let n = surface.n;
+        let prev = path.len() - 1;
+        let mut vertex = Vertex {
+            kind: Kind::Surface {
+                color: mat.color,
+                emission: mat.emission,
+                area: hit.area,
+            },
+            p: surface.p,
+            n: Some(n),
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        };
+        vertex.pdf_fwd = path[prev].convert(pdf_dir, &vertex);
+        path.push(vertex);
+        let current = path.len() - 1;
+
+        // same lobes as the path tracer, only diffuse bounces have a density.
+        // the last vertex picks one too, connections to it need to know
+        let scatter = if random::<f32>() < mat.metalness {
+            path[current].delta = true;
+            let reflected = ray.mirror(n).dir + random_vec_in_unit_sphere() * mat.roughness;
+            (reflected.dot(n) > 0.0).then_some((reflected, mat.color, 0.0, 0.0))
+        } else if random::<f32>() < mat.transmission {
+            path[current].delta = true;
+            let eta = if surface.front_face {
+                1.0 / mat.ior
+            } else {
+                mat.ior
+            };
+            let reflectance = fresnel_dielectric(ray.dir.dot(n), eta);
+            match ray.refract(n, eta) {
+                Some(refracted) if random::<f32>() >= reflectance => {
+                    Some((refracted.dir, mat.color, 0.0, 0.0))
+                }
+                _ => Some((ray.mirror(n).dir, Color::WHITE, 0.0, 0.0)),
+            }
+        } else {
+            let (dir, pdf) = cosine_hemisphere(n);
+            let pdf_rev = cosine_hemisphere_pdf(n.dot(-ray.dir));
+            Some((dir, mat.color, pdf, pdf_rev))
+        };
+        if bounce + 1 == max_depth {
+            break;
+        }
+        let Some((dir, tint, pdf_fwd, pdf_rev)) = scatter else {
+            break;
+        };
+
+        beta = beta * tint;
+        if beta.is_black() {
+            break;
+        }
+        path[prev].pdf_rev = path[current].convert(pdf_rev, &path[prev]);
+        pdf_dir = pdf_fwd;
+        ray = Ray {
+            pos: surface.p,
+            dir,
+            ..ray
+        };
+    }
+    Color::BLACK
+}
+
+// the sun can't be hit or start light paths, so sampling it from the camera
+// path is the only way to find it
+fn sample_suns(scene: &Scene, vertex: &Vertex, time: f32) -> Color {
+    let (Kind::Surface { color, .. }, Some(n)) = (vertex.kind, vertex.n) else {
+        return Color::BLACK;
+    };
+    if vertex.delta {
+        return Color::BLACK;
+    }
+    scene
+        .lights
+        .iter()
+        .filter(|l| matches!(l, Light::Sun { .. }))
+        .fold(Color::BLACK, |acc, sun| {
+            let Some(sample) = sun.sample(vertex.p) else {
+                return acc;
+            };
+            let cos = sample.dir.dot(n);
+            if cos <= 0.0 || !scene.visible(vertex.p, sample.dir, sample.dist, time) {
+                return acc;
+            }
+            acc + vertex.beta * color * sample.li * (cos * FRAC_1_PI / sample.pdf)
+        })
+}
+
+// light carried by the path made of the first `s` light and `t` camera
+// vertices, already weighed against the other ways of building it
+fn connect(
+    scene: &Scene,
+    light: &[Vertex],
+    camera: &[Vertex],
+    s: usize,
+    t: usize,
+    light_count: usize,
+    time: f32,
+) -> Color {
+    let pt = &camera[t - 1];
+    if s == 0 {
+        // the camera path ran into a light by itself
+        let Kind::Surface { emission, area, .. } = pt.kind else {
+            return Color::BLACK;
+        };
+        if emission.is_black() {
+            return Color::BLACK;
+        }
+        let l = pt.beta * emission;
+        // emissive planes aren't lights, there is no other way to find them
+        if area.is_infinite() || light_count == 0 {
+            return l;
+        }
+        return l * mis_weight(light, camera, s, t, light_count);
+    }
+
+    let qs = &light[s - 1];
+    if pt.delta || qs.delta {
+        return Color::BLACK;
+    }
+    let q_prev = (s > 1).then(|| light[s - 2].p);
+    let l = qs.beta * qs.f(q_prev, pt.p) * pt.f(Some(camera[t - 2].p), qs.p) * pt.beta;
+    if l.is_black() {
+        return Color::BLACK;
+    }
+
+    let d = pt.p - qs.p;
+    let dist = d.length();
+    let dir = d / dist;
+    let mut g = 1.0 / (dist * dist);
+    if let Some(n) = qs.n {
+        g *= n.dot(dir).abs();
+    }
+    if let Some(n) = pt.n {
+        g *= n.dot(dir).abs();
+    }
+    if g <= 0.0 || !scene.visible(qs.p, dir, dist - MIN_HIT_DIST, time) {
+        return Color::BLACK;
+    }
+    l * (g * mis_weight(light, camera, s, t, light_count))
+}
+
+// power heuristic over all strategies that could have built the same path,
+// computed from ratios of the densities on the way there
+fn mis_weight(light: &[Vertex], camera: &[Vertex], s: usize, t: usize, light_count: usize) -> f32 {
+    // (fwd, rev, delta) of every vertex as they are for this connection
+    let mut lv: Vec<(f32, f32, bool)> = light[..s]
+        .iter()
+        .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+        .collect();
+    let mut cv: Vec<(f32, f32, bool)> = camera[..t]
+        .iter()
+        .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+        .collect();
+
+    let pt = &camera[t - 1];
+    let pt_minus = &camera[t - 2];
+    let qs = s.checked_sub(1).map(|i| &light[i]);
+    let qs_minus = s.checked_sub(2).map(|i| &light[i]);
+
+    cv[t - 1].1 = match qs {
+        Some(qs) => qs.pdf(qs_minus.map(|v| v.p), pt),
+        None => pt.pdf_light_origin(light_count),
+    };
+    cv[t - 2].1 = match qs {
+        Some(qs) => pt.pdf(Some(qs.p), pt_minus),
+        None => pt.pdf_light(pt_minus),
+    };
+    if let Some(qs) = qs {
+        lv[s - 1].1 = pt.pdf(Some(pt_minus.p), qs);
+    }
+    if let (Some(qs), Some(qs_minus)) = (qs, qs_minus) {
+        lv[s - 2].1 = qs.pdf(Some(pt.p), qs_minus);
+    }
+
+    let mut sum = 0.0;
+    // moving vertices from the camera path to the light path, stopping before
+    // the strategy that would connect to the camera
+    let mut ratio = 1.0;
+    for i in (2..t).rev() {
+        ratio *= remap0(cv[i].1) / remap0(cv[i].0);
+        if !cv[i].2 && !cv[i - 1].2 {
+            sum += ratio * ratio;
+        }
+    }
+    // and the other way round
+    let mut ratio = 1.0;
+    for i in (0..s).rev() {
+        ratio *= remap0(lv[i].1) / remap0(lv[i].0);
+        let delta_before = match i {
+            0 => matches!(light[0].kind, Kind::Light(l) if l.is_delta_position()),
+            _ => lv[i - 1].2,
+        };
+        if !lv[i].2 && !delta_before {
+            sum += ratio * ratio;
+        }
+    }
+    1.0 / (1.0 + sum)
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::Bdpt;
+    use crate::camera::Camera;
+    use crate::integrator::{Integrator, PathTracer};
+    use crate::light::Light;
+    use crate::math::{Color, Material, Plane, Ray, Sphere};
+    use crate::scene::SceneBuilder;
+    use crate::settings::RenderSettings;
+
+    fn mean(integrator: &dyn Integrator, scene: &crate::scene::Scene, ray: Ray) -> f32 {
+        let count = 20000;
+        let sum: f32 = (0..count).map(|_| integrator.li(scene, ray).r).sum();
+        sum / count as f32
+    }
+
+    #[test]
+    fn furnace() {
+        // see the path tracer's furnace test, cut off after 12 bounces
+        let camera = Camera {
+            pos: Vec3::ZERO,
+            dir: Vec3::Z,
+            ..Default::default()
+        };
+        let scene = SceneBuilder::new()
+            .object(Sphere {
+                pos: Vec3::ZERO,
+                rad: 1.0,
+                material: Material {
+                    color: Color::WHITE * 0.5,
+                    emission: Color::WHITE,
+                    ..Default::default()
+                },
+            })
+            .build(&camera);
+        let ray = Ray {
+            pos: Vec3::ZERO,
+            dir: Vec3::new(0.3, 0.5, 1.0),
+            ..Default::default()
+        };
+        let expected = 2.0 * (1.0 - 0.5f32.powi(13));
+        let result = mean(&Bdpt { max_depth: 12 }, &scene, ray);
+        assert!((result - expected).abs() < 0.05, "{result}");
+    }
+
+    #[test]
+    fn matches_path_tracer() {
+        let camera = Camera {
+            pos: Vec3::new(0.0, 1.0, 0.0),
+            dir: Vec3::Z,
+            ..Default::default()
+        };
+        let scene = SceneBuilder::new()
+            .object(Plane {
+                pos: Vec3::ZERO,
+                norm: Vec3::Y,
+                material: Material::default(),
+            })
+            .object(Sphere {
+                pos: Vec3::new(0.5, 1.0, 5.0),
+                rad: 0.8,
+                material: Material::default(),
+            })
+            .light(Light::point(Vec3::new(-1.0, 3.0, 4.0), Color::WHITE, 10.0))
+            .sky_color(Color::BLACK)
+            .horizon_color(Color::BLACK)
+            .build(&camera);
+        // looking at the floor next to the sphere
+        let ray = Ray {
+            pos: Vec3::ZERO,
+            dir: Vec3::new(0.2, -0.3, 1.0),
+            ..Default::default()
+        };
+        let settings = RenderSettings {
+            max_bounces: 6,
+            ..Default::default()
+        };
+        let path = mean(&PathTracer::new(&settings), &scene, ray);
+        let bdpt = mean(&Bdpt { max_depth: 6 }, &scene, ray);
+        assert!((path - bdpt).abs() < 0.03 * path, "{path} vs {bdpt}");
+    }
+}
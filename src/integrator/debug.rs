@@ -0,0 +1,101 @@
+use super::Integrator;
+use crate::math::{Color, Ray};
+use crate::scene::Scene;
+
+// distance that shows up as middle grey in the depth view
+const DEPTH_SCALE: f32 = 10.0;
+
+/// Shows a property of the first hit instead of light, black where the ray
+/// misses everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugView {
+    /// outward normals in view space, mapped from -1..1 to 0..1
+    Normals,
+    /// white up close, fading to black in the distance
+    Depth,
+    /// u in red, v in green
+    Uv,
+    /// a different color for every material
+    MaterialId,
+}
+
+impl Integrator for DebugView {
+    fn li(&self, scene: &Scene, mut ray: Ray) -> Color {
+        ray.normalize();
+        let Some(hit) = scene.find_closest(ray) else {
+            return Color::BLACK;
+        };
+        match self {
+            DebugView::Normals => {
+                let n = hit.normal.normalize() * 0.5 + 0.5;
+                Color {
+                    r: n.x,
+                    g: n.y,
+                    b: n.z,
+                }
+            }
+            DebugView::Depth => Color::WHITE * (DEPTH_SCALE / (DEPTH_SCALE + hit.t)),
+            DebugView::Uv => Color {
+                r: hit.uv.x,
+                g: hit.uv.y,
+                b: 0.0,
+            },
+            DebugView::MaterialId => id_color(hit.material.id),
+        }
+    }
+}
+
+// spreads consecutive ids over very different hues
+fn id_color(id: u32) -> Color {
+    let hash = (id + 1).wrapping_mul(0x9e37_79b9);
+    let channel = |shift: u32| ((hash >> shift) & 0xff) as f32 / 255.0;
+    Color {
+        r: channel(0),
+        g: channel(8),
+        b: channel(16),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::DebugView;
+    use crate::camera::Camera;
+    use crate::integrator::Integrator;
+    use crate::math::{Color, Material, Plane, Ray};
+    use crate::scene::SceneBuilder;
+
+    #[test]
+    fn floor_below() {
+        let scene = SceneBuilder::new()
+            .object(Plane {
+                pos: Vec3::new(0.0, -10.0, 0.0),
+                norm: Vec3::Y,
+                material: Material::default(),
+            })
+            .build(&Camera::default());
+        let down = Ray {
+            pos: Vec3::ZERO,
+            dir: -Vec3::Y,
+            ..Default::default()
+        };
+        let up = Ray {
+            dir: Vec3::Y,
+            ..down
+        };
+
+        let normal = DebugView::Normals.li(&scene, down);
+        assert!((normal.r - 0.5).abs() < 1e-5 && (normal.g - 1.0).abs() < 1e-5);
+        let depth = DebugView::Depth.li(&scene, down);
+        assert!((depth.r - 0.5).abs() < 1e-5);
+        for view in [
+            DebugView::Normals,
+            DebugView::Depth,
+            DebugView::Uv,
+            DebugView::MaterialId,
+        ] {
+            assert_eq!(view.li(&scene, up), Color::BLACK);
+        }
+    }
+}
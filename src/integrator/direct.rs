@@ -0,0 +1,89 @@
+use std::f32::consts::FRAC_1_PI;
+
+use super::{sample_lights, weighted_emission, Integrator, Surface};
+use crate::math::{Color, Ray};
+use crate::sampling::cosine_hemisphere;
+use crate::scene::Scene;
+
+/// Light reaching the first surface straight from the light sources and the
+/// sky, every surface is treated as diffuse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectLighting;
+
+impl Integrator for DirectLighting {
+    fn li(&self, scene: &Scene, mut ray: Ray) -> Color {
+        ray.normalize();
+        let Some(hit) = scene.find_closest(ray) else {
+            return scene.sky(ray.dir);
+        };
+        let mat = hit.material;
+        let surface = Surface::new(ray, &hit);
+
+        // sample the lights and one bounce, the bounce only counts if it
+        // finds emission or the sky straight away
+        let direct = sample_lights(scene, surface.p, surface.n, ray.time) * FRAC_1_PI;
+        let (dir, pdf) = cosine_hemisphere(surface.n);
+        let mut bounce = Ray {
+            pos: surface.p,
+            dir,
+            ..ray
+        };
+        bounce.normalize();
+        let found = match scene.find_closest(bounce) {
+            Some(next) => weighted_emission(scene, bounce, &next, pdf),
+            None => scene.sky(dir),
+        };
+
+        mat.emission + (direct + found) * mat.color
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::DirectLighting;
+    use crate::camera::Camera;
+    use crate::integrator::Integrator;
+    use crate::light::Light;
+    use crate::math::{Color, Material, Plane, Ray, Sphere};
+    use crate::scene::SceneBuilder;
+
+    #[test]
+    fn lamp_and_shadow() {
+        let camera = Camera::default();
+        let floor = || Plane {
+            pos: -Vec3::Y,
+            norm: Vec3::Y,
+            material: Material::default(),
+        };
+        let lamp = Light::point(Vec3::new(0.0, 1.0, 3.0), Color::WHITE, 5.0);
+        let lit = SceneBuilder::new()
+            .object(floor())
+            .light(lamp)
+            .sky_color(Color::BLACK)
+            .horizon_color(Color::BLACK)
+            .build(&camera);
+        let shadowed = SceneBuilder::new()
+            .object(floor())
+            .object(Sphere {
+                pos: Vec3::new(0.0, 0.0, 3.0),
+                rad: 0.5,
+                material: Material::default(),
+            })
+            .light(lamp)
+            .sky_color(Color::BLACK)
+            .horizon_color(Color::BLACK)
+            .build(&camera);
+        let at = |x: f32| Ray {
+            pos: Vec3::ZERO,
+            dir: Vec3::new(x, -1.0, 3.0),
+            ..Default::default()
+        };
+
+        let below = DirectLighting.li(&lit, at(0.0)).r;
+        let aside = DirectLighting.li(&lit, at(2.0)).r;
+        assert!(below > aside && aside > 0.0, "{below} {aside}");
+        assert_eq!(DirectLighting.li(&shadowed, at(0.0)), Color::BLACK);
+    }
+}
@@ -0,0 +1,439 @@
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use glam::Vec2;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rayon::prelude::*;
+
+use super::path::{trace_path, PathTracer};
+use super::{Film, Integrator};
+use crate::math::{Color, Ray};
+use crate::rng::{self, random};
+use crate::scene::Scene;
+use crate::settings::RenderSettings;
+
+// standard deviation of the small mutations
+const SIGMA: f32 = 0.01;
+
+/// Primary sample space metropolis light transport. The path tracer builds
+/// its paths from a list of random numbers, chains of paths get explored by
+/// nudging those numbers a little or now and then replacing all of them.
+/// Every step lands wherever on the image its path goes and bright paths get
+/// visited more often, so once a chain finds light through some narrow gap
+/// it keeps exploring the paths around it.
+///
+/// Noise is blotchy instead of grainy and it needs some samples per pixel
+/// before the chains have spread over the whole image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metropolis {
+    pub path: PathTracer,
+    pub bootstrap_samples: usize,
+    pub chains: usize,
+    /// chance of a mutation replacing all the numbers of a path
+    pub large_step: f32,
+}
+
+impl Metropolis {
+    pub fn new(settings: &RenderSettings) -> Self {
+        Self {
+            path: PathTracer::new(settings),
+            bootstrap_samples: settings.mlt_bootstrap_samples,
+            chains: settings.mlt_chains,
+            large_step: settings.mlt_large_step,
+        }
+    }
+
+    // builds the path from the numbers in `samples`
+    fn splat(&self, scene: &Scene, film: &Film, samples: &Rc<RefCell<PrimarySamples>>) -> Splat {
+        rng::with_source(Box::new(Replay(samples.clone())), || {
+            let size = film.camera.resolution.as_vec2();
+            let pixel = Vec2::new(random::<f32>(), random::<f32>()) * size;
+            let color = match film.camera.generate_ray(pixel.as_uvec2(), pixel.fract()) {
+                Some(ray) => trace_path(&self.path, scene, ray, None),
+                None => Color::BLACK,
+            };
+            Splat {
+                pixel,
+                color: if color.luminance().is_finite() {
+                    color
+                } else {
+                    Color::BLACK
+                },
+            }
+        })
+    }
+
+    fn run_chain(
+        &self,
+        scene: &Scene,
+        film: &Film,
+        start: u64,
+        mutations: u64,
+        rng: &mut StdRng,
+        image: &mut [Color],
+    ) {
+        let samples = Rc::new(RefCell::new(PrimarySamples::new(start, self.large_step)));
+        let mut current = self.splat(scene, film, &samples);
+        for _ in 0..mutations {
+            samples.borrow_mut().mutate();
+            let proposed = self.splat(scene, film, &samples);
+            let accept = if current.brightness() > 0.0 {
+                (proposed.brightness() / current.brightness()).min(1.0)
+            } else {
+                1.0
+            };
+
+            // both paths count as much as they are likely to be kept,
+            // smoother than only counting the one that is
+            if accept > 0.0 {
+                add_splat(image, film, proposed, accept / proposed.brightness());
+            }
+            if accept < 1.0 {
+                add_splat(image, film, current, (1.0 - accept) / current.brightness());
+            }
+
+            if rng.gen::<f32>() < accept {
+                current = proposed;
+                samples.borrow_mut().accept();
+            } else {
+                samples.borrow_mut().reject();
+            }
+        }
+    }
+}
+
+impl Integrator for Metropolis {
+    fn li(&self, scene: &Scene, ray: Ray) -> Color {
+        trace_path(&self.path, scene, ray, None)
+    }
+
+    fn render_image(&self, scene: &Scene, film: &Film) -> Option<Vec<Color>> {
+        let seed = random::<u64>();
+        let size = film.camera.resolution;
+        let pixel_count = (size.x * size.y) as usize;
+
+        // brightness of completely random paths, it tells how bright the
+        // whole image is and gives the chains bright paths to start at
+        let bootstrap: Vec<f32> = (0..self.bootstrap_samples.max(1))
+            .into_par_iter()
+            .map(|i| {
+                let samples = PrimarySamples::new(stream_seed(seed, i), self.large_step);
+                self.splat(scene, film, &Rc::new(RefCell::new(samples)))
+                    .brightness()
+            })
+            .collect();
+        let cdf: Vec<f64> = bootstrap
+            .iter()
+            .scan(0.0, |sum, &b| {
+                *sum += b as f64;
+                Some(*sum)
+            })
+            .collect();
+        let total = cdf[cdf.len() - 1];
+        if total <= 0.0 {
+            return Some(vec![Color::BLACK; pixel_count]);
+        }
+        let brightness = total / bootstrap.len() as f64;
+
+        let mutations = pixel_count as u64 * film.samples as u64;
+        let chains = (self.chains as u64).clamp(1, mutations.max(1));
+        let finished = AtomicUsize::new(0);
+        let image = (0..chains)
+            .into_par_iter()
+            .fold(
+                || vec![Color::BLACK; pixel_count],
+                |mut image, chain| {
+                    let mut rng =
+                        StdRng::seed_from_u64(stream_seed(seed, bootstrap.len() + chain as usize));
+                    let pick = rng.gen::<f64>() * total;
+                    let start = cdf.partition_point(|&c| c <= pick).min(cdf.len() - 1);
+                    let count = mutations / chains + u64::from(chain < mutations % chains);
+                    // the same seed gives the same numbers as in the
+                    // bootstrap, so the chain starts on that path
+                    let start = stream_seed(seed, start);
+                    self.run_chain(scene, film, start, count, &mut rng, &mut image);
+                    let done = finished.fetch_add(1, Ordering::Relaxed) + 1;
+                    (film.progress)(done as f32 / chains as f32);
+                    image
+                },
+            )
+            .reduce(
+                || vec![Color::BLACK; pixel_count],
+                |mut a, b| {
+                    for (a, b) in a.iter_mut().zip(b) {
+                        *a = *a + b;
+                    }
+                    a
+                },
+            );
+
+        let scale = (brightness / film.samples as f64) as f32;
+        Some(image.into_iter().map(|c| c * scale).collect())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Splat {
+    pixel: Vec2,
+    color: Color,
+}
+
+impl Splat {
+    // what the chains try to visit in proportion to
+    fn brightness(&self) -> f32 {
+        self.color.luminance()
+    }
+}
+
+fn add_splat(image: &mut [Color], film: &Film, splat: Splat, weight: f32) {
+    let size = film.camera.resolution;
+    let pixel = splat.pixel.as_uvec2().min(size - 1);
+    let i = (pixel.y * size.x + pixel.x) as usize;
+    image[i] = image[i] + splat.color * weight;
+}
+
+fn stream_seed(seed: u64, stream: usize) -> u64 {
+    seed.wrapping_add((stream as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: f32,
+    /// mutation the value was last changed in
+    modified: u64,
+    // from before the current mutation, in case it gets rejected
+    backup: f32,
+    modified_backup: u64,
+}
+
+/// The random numbers a path is built from. They are made lazily, paths
+/// use however many they need, and numbers a path didn't use for a while
+/// catch up on the mutations they missed when it does use them again.
+#[derive(Debug, Clone)]
+struct PrimarySamples {
+    values: Vec<PrimarySample>,
+    rng: StdRng,
+    large_step_chance: f32,
+    large_step: bool,
+    mutation: u64,
+    last_large_step: u64,
+    next: usize,
+}
+
+impl PrimarySamples {
+    // starts out with completely random numbers, always the same ones for
+    // the same seed
+    fn new(seed: u64, large_step_chance: f32) -> Self {
+        Self {
+            values: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+            large_step_chance,
+            large_step: true,
+            mutation: 0,
+            last_large_step: 0,
+            next: 0,
+        }
+    }
+
+    fn mutate(&mut self) {
+        self.mutation += 1;
+        self.large_step = self.rng.gen::<f32>() < self.large_step_chance;
+        self.next = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.mutation;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in &mut self.values {
+            if sample.modified == self.mutation {
+                sample.value = sample.backup;
+                sample.modified = sample.modified_backup;
+            }
+        }
+        self.mutation -= 1;
+    }
+
+    fn next(&mut self) -> f32 {
+        if self.next == self.values.len() {
+            // never used before, small steps alone would keep it near 0
+            let value = self.rng.gen();
+            self.values.push(PrimarySample {
+                value,
+                modified: self.mutation,
+                backup: value,
+                modified_backup: self.mutation,
+            });
+        }
+        let sample = &mut self.values[self.next];
+        self.next += 1;
+
+        // a large step happened since the path last used this one
+        if sample.modified < self.last_large_step {
+            sample.value = self.rng.gen();
+            sample.modified = self.last_large_step;
+        }
+        sample.backup = sample.value;
+        sample.modified_backup = sample.modified;
+        if self.large_step {
+            sample.value = self.rng.gen();
+        } else {
+            // all the small steps it missed at once, their sum is normal
+            // distributed too
+            let steps = (self.mutation - sample.modified) as f32;
+            let offset = gaussian(&mut self.rng) * SIGMA * steps.sqrt();
+            sample.value = (sample.value + offset).rem_euclid(1.0);
+        }
+        sample.modified = self.mutation;
+        sample.value
+    }
+}
+
+// lets `rng::random` hand out the numbers of a chain
+struct Replay(Rc<RefCell<PrimarySamples>>);
+
+impl RngCore for Replay {
+    fn next_u32(&mut self) -> u32 {
+        // `as` saturates, a value rounded up to 1 stays below 1 as a float
+        (self.0.borrow_mut().next() as f64 * 4_294_967_296.0) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) << 32 | self.next_u32() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// standard normal distributed, box muller
+fn gaussian(rng: &mut StdRng) -> f32 {
+    let r = (-2.0 * (1.0 - rng.gen::<f32>()).ln()).sqrt();
+    r * (2.0 * PI * rng.gen::<f32>()).cos()
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{UVec2, Vec2, Vec3};
+
+    use super::Metropolis;
+    use crate::camera::Camera;
+    use crate::integrator::{Film, Integrator, PathTracer};
+    use crate::light::Light;
+    use crate::math::{Color, Material, Plane, Sphere};
+    use crate::scene::SceneBuilder;
+    use crate::settings::RenderSettings;
+
+    #[test]
+    fn matches_path_tracer() {
+        // a floor under a lamp, brightest in the middle of the image
+        let camera = Camera {
+            pos: Vec3::new(0.0, 1.0, 0.0),
+            look_at: Some(Vec3::new(0.0, 0.0, 3.0)),
+            resolution: UVec2::new(4, 4),
+            ..Default::default()
+        };
+        let scene = SceneBuilder::new()
+            .object(Plane {
+                pos: Vec3::ZERO,
+                norm: Vec3::Y,
+                material: Material::default(),
+            })
+            .light(Light::point(Vec3::new(0.0, 2.0, 3.0), Color::WHITE, 5.0))
+            .sky_color(Color::BLACK)
+            .horizon_color(Color::BLACK)
+            .build(&camera);
+        let film = Film {
+            camera: &camera,
+            samples: 4000,
+            progress: &|_| {},
+        };
+        let settings = RenderSettings {
+            mlt_bootstrap_samples: 10_000,
+            mlt_chains: 64,
+            ..Default::default()
+        };
+        let image = Metropolis::new(&settings)
+            .render_image(&scene, &film)
+            .unwrap();
+
+        let path = PathTracer::new(&settings);
+        for y in 0..4 {
+            for x in 0..4 {
+                let count = 4000;
+                let sum: f32 = (0..count)
+                    .map(|i| {
+                        let offset = Vec2::new((i % 63) as f32 / 63.0, (i % 61) as f32 / 61.0);
+                        let pixel = UVec2::new(x as u32, y as u32);
+                        path.li(&scene, camera.generate_ray(pixel, offset).unwrap())
+                            .r
+                    })
+                    .sum();
+                let expected = sum / count as f32;
+                let result = image[y * 4 + x].r;
+                assert!(
+                    (result - expected).abs() < 0.1 * expected + 0.01,
+                    "{x} {y}: {result} {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn finishes_with_mirrors() {
+        // mirror bounces use more random numbers than the first paths did
+        let camera = Camera {
+            pos: Vec3::new(0.0, 1.0, 0.0),
+            look_at: Some(Vec3::new(0.0, 0.5, 3.0)),
+            resolution: UVec2::new(4, 4),
+            ..Default::default()
+        };
+        let scene = SceneBuilder::new()
+            .object(Plane {
+                pos: Vec3::ZERO,
+                norm: Vec3::Y,
+                material: Material::default(),
+            })
+            .object(Sphere {
+                pos: Vec3::new(0.0, 0.5, 3.0),
+                rad: 0.5,
+                material: Material {
+                    color: Color::WHITE,
+                    metalness: 1.0,
+                    ..Default::default()
+                },
+            })
+            .light(Light::point(Vec3::new(1.0, 2.0, 2.0), Color::WHITE, 5.0))
+            .build(&camera);
+        let film = Film {
+            camera: &camera,
+            samples: 200,
+            progress: &|_| {},
+        };
+        let settings = RenderSettings {
+            mlt_bootstrap_samples: 1000,
+            mlt_chains: 16,
+            ..Default::default()
+        };
+        let image = Metropolis::new(&settings)
+            .render_image(&scene, &film)
+            .unwrap();
+        assert!(image.iter().all(|c| c.r.is_finite() && c.r >= 0.0));
+        assert!(image.iter().any(|c| c.r > 0.0));
+    }
+}
@@ -0,0 +1,226 @@
+use std::f32::consts::FRAC_1_PI;
+
+use glam::Vec3;
+
+use super::{sample_lights, weighted_emission, Integrator, Surface};
+use crate::math::{fresnel_dielectric, random_vec_in_unit_sphere, Color, Ray};
+use crate::rng::random;
+use crate::sampling::cosine_hemisphere;
+use crate::scene::Scene;
+use crate::settings::RenderSettings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lobe {
+    Diffuse,
+    Specular,
+    Transmission,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BounceCount {
+    total: u32,
+    diffuse: u32,
+    specular: u32,
+    transmission: u32,
+}
+
+impl BounceCount {
+    // counts the bounce, false if it goes over one of the limits
+    fn add(&mut self, lobe: Lobe, settings: &PathTracer) -> bool {
+        self.total += 1;
+        let (count, max) = match lobe {
+            Lobe::Diffuse => (&mut self.diffuse, settings.max_diffuse_bounces),
+            Lobe::Specular => (&mut self.specular, settings.max_specular_bounces),
+            Lobe::Transmission => (&mut self.transmission, settings.max_transmission_bounces),
+        };
+        *count += 1;
+        self.total <= settings.max_bounces && *count <= max
+    }
+}
+
+/// Unbiased global illumination, follows one path per camera ray and samples
+/// the lights at every diffuse bounce.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathTracer {
+    pub max_bounces: u32,
+    pub max_diffuse_bounces: u32,
+    pub max_specular_bounces: u32,
+    pub max_transmission_bounces: u32,
+    /// paths longer than this get randomly cut off when they carry little light
+    pub roulette_depth: u32,
+}
+
+impl PathTracer {
+    pub fn new(settings: &RenderSettings) -> Self {
+        Self {
+            max_bounces: settings.max_bounces,
+            max_diffuse_bounces: settings.max_diffuse_bounces,
+            max_specular_bounces: settings.max_specular_bounces,
+            max_transmission_bounces: settings.max_transmission_bounces,
+            roulette_depth: settings.roulette_depth,
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn li(&self, scene: &Scene, ray: Ray) -> Color {
+        trace_path(self, scene, ray, None)
+    }
+}
+
+/// Light arriving at `p` with normal `n` through caustics, per area. Once it
+/// is given, paths that reach a light through mirrors or glass after a
+/// diffuse bounce are left to it.
+pub(super) type Caustics<'a> = &'a dyn Fn(Vec3, Vec3) -> Color;
+
+pub(super) fn trace_path(
+    settings: &PathTracer,
+    scene: &Scene,
+    mut ray: Ray,
+    caustics: Option<Caustics>,
+) -> Color {
+    let mut radiance = Color::BLACK;
+    // how much of the light found at the current vertex reaches the camera
+    let mut throughput = Color::WHITE;
+    // density the last diffuse bounce picked its direction with, `None` after
+    // specular bounces and for camera rays
+    let mut bsdf_pdf: Option<f32> = None;
+    let mut bounces = BounceCount::default();
+    let mut had_diffuse = false;
+
+    loop {
+        // the primitives report distances along the normalized direction
+        ray.normalize();
+        let Some(hit) = scene.find_closest(ray) else {
+            return radiance + scene.sky(ray.dir) * throughput;
+        };
+        let mat = hit.material;
+        let Surface {
+            p: res_p,
+            n,
+            front_face,
+        } = Surface::new(ray, &hit);
+
+        // emission the last bounce could also have found by sampling the
+        // lights, weighed against that with multiple importance sampling
+        let emission = match bsdf_pdf {
+            Some(pdf) => weighted_emission(scene, ray, &hit, pdf),
+            // found through a caustic, the photons already brought it
+            None if caustics.is_some() && had_diffuse && hit.area.is_finite() => Color::BLACK,
+            None => mat.emission,
+        };
+        radiance = radiance + emission * throughput;
+
+        let (lobe, dir, tint) = if random::<f32>() < mat.metalness {
+            let reflected = ray.mirror(n).dir + random_vec_in_unit_sphere() * mat.roughness;
+            // fuzzed below the surface, the ray gets absorbed
+            if reflected.dot(n) <= 0.0 {
+                return radiance;
+            }
+            bsdf_pdf = None;
+            (Lobe::Specular, reflected, mat.color)
+        } else if random::<f32>() < mat.transmission {
+            let eta = if front_face { 1.0 / mat.ior } else { mat.ior };
+            let reflectance = fresnel_dielectric(ray.dir.dot(n), eta);
+            bsdf_pdf = None;
+            match ray.refract(n, eta) {
+                Some(refracted) if random::<f32>() >= reflectance => {
+                    (Lobe::Transmission, refracted.dir, mat.color)
+                }
+                // total internal reflection or fresnel reflection, which
+                // doesn't pick up the color of the material
+                _ => (Lobe::Transmission, ray.mirror(n).dir, Color::WHITE),
+            }
+        } else {
+            // light sources are sampled directly on diffuse surfaces instead
+            // of waiting for a random bounce to find them
+            let mut direct = sample_lights(scene, res_p, n, ray.time);
+            if let Some(caustics) = caustics {
+                direct = direct + caustics(res_p, n);
+            }
+            radiance = radiance + direct * mat.color * throughput * FRAC_1_PI;
+            had_diffuse = true;
+            let (dir, pdf) = cosine_hemisphere(n);
+            bsdf_pdf = Some(pdf);
+            // the cosine and 1/pi of the brdf cancel out with the pdf
+            (Lobe::Diffuse, dir, mat.color)
+        };
+
+        if !bounces.add(lobe, settings) {
+            return radiance;
+        }
+        throughput = throughput * tint;
+
+        // randomly end paths that carry little light, the survivors make up
+        // for the ones that got cut off
+        if bounces.total > settings.roulette_depth {
+            let survive = throughput.max_channel().clamp(0.05, 0.95);
+            if random::<f32>() >= survive {
+                return radiance;
+            }
+            throughput = throughput * (1.0 / survive);
+        }
+
+        ray = Ray {
+            pos: res_p,
+            dir,
+            ..ray
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::PathTracer;
+    use crate::camera::Camera;
+    use crate::integrator::Integrator;
+    use crate::math::{Color, Material, Ray, Sphere};
+    use crate::scene::SceneBuilder;
+    use crate::settings::RenderSettings;
+
+    #[test]
+    fn furnace() {
+        // inside a glowing sphere that reflects half of the light, every
+        // direction sees the emission plus all of its bounces: 1 / (1 - 0.5)
+        let grey = Color {
+            r: 0.5,
+            g: 0.5,
+            b: 0.5,
+        };
+        let camera = Camera {
+            pos: Vec3::ZERO,
+            dir: Vec3::Z,
+            ..Default::default()
+        };
+        let scene = SceneBuilder::new()
+            .object(Sphere {
+                pos: Vec3::ZERO,
+                rad: 1.0,
+                material: Material {
+                    color: grey,
+                    emission: Color::WHITE,
+                    ..Default::default()
+                },
+            })
+            .build(&camera);
+        let integrator = PathTracer {
+            max_diffuse_bounces: 100,
+            ..PathTracer::new(&RenderSettings::default())
+        };
+
+        let count = 20000;
+        let sum = (0..count).fold(0.0, |acc, i| {
+            let angle = i as f32;
+            let ray = Ray {
+                pos: Vec3::ZERO,
+                dir: Vec3::new(angle.sin(), angle.cos(), 0.3),
+                ..Default::default()
+            };
+            acc + integrator.li(&scene, ray).r
+        });
+        let mean = sum / count as f32;
+        assert!((mean - 2.0).abs() < 0.05, "{mean}");
+    }
+}
@@ -0,0 +1,248 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use super::path::{trace_path, PathTracer};
+use super::{Integrator, Surface};
+use crate::kdtree::KdTree;
+use crate::light::Light;
+use crate::math::{fresnel_dielectric, random_vec_in_unit_sphere, Color, Ray};
+use crate::rng::random;
+use crate::scene::Scene;
+use crate::settings::RenderSettings;
+
+// how fast the gather radius shrinks between passes, between 0 and 1. lower
+// shrinks faster and gets sharp sooner, higher averages away noise sooner
+const ALPHA: f32 = 2.0 / 3.0;
+
+#[derive(Debug, Clone, Copy)]
+struct Photon {
+    pos: Vec3,
+    /// towards where the photon came from
+    dir: Vec3,
+    power: Color,
+}
+
+/// Path tracer that gets its caustics from photons. Every pass shoots
+/// photons from the lights through mirrors and glass, keeps the ones that
+/// land on diffuse surfaces and gathers them around diffuse hits of camera
+/// paths. The gather radius shrinks with every pass so the blur of the
+/// estimate goes away as the passes are averaged (progressive photon mapping).
+///
+/// The sun can't shoot photons, its caustics are missing.
+#[derive(Debug, Clone)]
+pub struct PhotonMapper {
+    pub path: PathTracer,
+    pub photons_per_pass: usize,
+    pub passes: u32,
+    /// gather radius of the first pass
+    pub radius: f32,
+    current_radius: f32,
+    photons: Vec<Photon>,
+    tree: KdTree,
+}
+
+impl PhotonMapper {
+    pub fn new(settings: &RenderSettings) -> Self {
+        Self {
+            path: PathTracer::new(settings),
+            photons_per_pass: settings.photons_per_pass,
+            passes: settings.photon_passes,
+            radius: settings.photon_radius,
+            current_radius: settings.photon_radius,
+            photons: Vec::new(),
+            tree: KdTree::default(),
+        }
+    }
+
+    fn shoot_photons(&mut self, scene: &Scene) {
+        self.photons.clear();
+        let lights: Vec<&Light> = scene
+            .lights
+            .iter()
+            .filter(|l| !matches!(l, Light::Sun { .. }))
+            .collect();
+        if lights.is_empty() {
+            return;
+        }
+        let max_depth = self
+            .path
+            .max_specular_bounces
+            .max(self.path.max_transmission_bounces)
+            .min(self.path.max_bounces);
+        // every photon carries its share of the power of all lights
+        let scale = lights.len() as f32 / self.photons_per_pass as f32;
+
+        for _ in 0..self.photons_per_pass {
+            let light = lights[(random::<f32>() * lights.len() as f32) as usize % lights.len()];
+            let Some(sample) = light.sample_emission() else {
+                continue;
+            };
+            if sample.pdf_pos <= 0.0 || sample.pdf_dir <= 0.0 {
+                continue;
+            }
+            let cos = sample.normal.map_or(1.0, |n| n.dot(sample.dir).abs());
+            let power = light.emitted(sample.normal, sample.dir)
+                * (cos * scale / (sample.pdf_pos * sample.pdf_dir));
+            // photons land where things are at some time the shutter is open
+            let ray = Ray {
+                pos: sample.pos,
+                dir: sample.dir,
+                time: scene.camera.sample_time(),
+            };
+            if let Some(photon) = trace_photon(scene, ray, power, max_depth) {
+                self.photons.push(photon);
+            }
+        }
+    }
+
+    // light per area arriving at `p` through caustics
+    fn gather(&self, p: Vec3, n: Vec3) -> Color {
+        let radius = self.current_radius;
+        let mut sum = Color::BLACK;
+        self.tree.within(p, radius, |i, _| {
+            let photon = &self.photons[i];
+            // don't pick up photons from the other side of thin walls
+            if photon.dir.dot(n) > 0.0 {
+                sum = sum + photon.power;
+            }
+        });
+        sum * (1.0 / (PI * radius * radius))
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn li(&self, scene: &Scene, ray: Ray) -> Color {
+        trace_path(&self.path, scene, ray, Some(&|p, n| self.gather(p, n)))
+    }
+
+    fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    fn prepare(&mut self, scene: &Scene, pass: u32) {
+        self.current_radius = if pass == 0 {
+            self.radius
+        } else {
+            let i = pass as f32;
+            self.current_radius * ((i + ALPHA) / (i + 1.0)).sqrt()
+        };
+        self.shoot_photons(scene);
+        let positions: Vec<Vec3> = self.photons.iter().map(|p| p.pos).collect();
+        self.tree = KdTree::build(&positions);
+    }
+}
+
+// follows a photon through mirrors and glass, it gets stored where it lands
+// on the first diffuse surface after at least one of those
+fn trace_photon(scene: &Scene, mut ray: Ray, mut power: Color, max_depth: u32) -> Option<Photon> {
+    let mut specular = false;
+    for _ in 0..=max_depth {
+        ray.normalize();
+        let hit = scene.find_closest(ray)?;
+        let mat = hit.material;
+        let surface = Surface::new(ray, &hit);
+        let n = surface.n;
+
+        let (dir, tint) = if random::<f32>() < mat.metalness {
+            let reflected = ray.mirror(n).dir + random_vec_in_unit_sphere() * mat.roughness;
+            if reflected.dot(n) <= 0.0 {
+                return None;
+            }
+            (reflected, mat.color)
+        } else if random::<f32>() < mat.transmission {
+            let eta = if surface.front_face {
+                1.0 / mat.ior
+            } else {
+                mat.ior
+            };
+            let reflectance = fresnel_dielectric(ray.dir.dot(n), eta);
+            match ray.refract(n, eta) {
+                Some(refracted) if random::<f32>() >= reflectance => (refracted.dir, mat.color),
+                _ => (ray.mirror(n).dir, Color::WHITE),
+            }
+        } else {
+            // the path tracer already covers light that reaches diffuse
+            // surfaces any other way
+            return specular.then_some(Photon {
+                pos: surface.p,
+                dir: -ray.dir,
+                power,
+            });
+        };
+
+        specular = true;
+        power = power * tint;
+        if power.is_black() {
+            return None;
+        }
+        ray = Ray {
+            pos: surface.p,
+            dir,
+            ..ray
+        };
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::PhotonMapper;
+    use crate::camera::Camera;
+    use crate::integrator::{Integrator, PathTracer};
+    use crate::light::Light;
+    use crate::math::{Color, Material, Plane, Ray, Sphere};
+    use crate::scene::SceneBuilder;
+    use crate::settings::RenderSettings;
+
+    #[test]
+    fn focuses_light_under_glass() {
+        // a glass ball under a small lamp throws a bright spot on the floor
+        // that the path tracer can only find through other bounces
+        let camera = Camera {
+            pos: Vec3::ZERO,
+            dir: Vec3::Z,
+            ..Default::default()
+        };
+        let scene = SceneBuilder::new()
+            .object(Plane {
+                pos: Vec3::ZERO,
+                norm: Vec3::Y,
+                material: Material::default(),
+            })
+            .object(Sphere {
+                pos: Vec3::new(0.0, 1.5, 0.0),
+                rad: 0.5,
+                material: Material {
+                    color: Color::WHITE,
+                    transmission: 1.0,
+                    ..Default::default()
+                },
+            })
+            .light(Light::point(Vec3::new(0.0, 3.0, 0.0), Color::WHITE, 1.0))
+            .sky_color(Color::BLACK)
+            .horizon_color(Color::BLACK)
+            .build(&camera);
+
+        let settings = RenderSettings {
+            photons_per_pass: 20000,
+            photon_radius: 0.1,
+            ..Default::default()
+        };
+        let mut photons = PhotonMapper::new(&settings);
+        photons.prepare(&scene, 0);
+        let path = PathTracer::new(&settings);
+
+        // straight down onto the floor below the ball, from just above it
+        let ray = Ray {
+            pos: Vec3::new(0.0, 0.01, 0.0),
+            dir: -Vec3::Y,
+            ..Default::default()
+        };
+        let lit_by_photons = (0..100).map(|_| photons.li(&scene, ray).r).sum::<f32>();
+        let lit_by_paths = (0..100).map(|_| path.li(&scene, ray).r).sum::<f32>();
+        assert!(lit_by_photons > 10.0 * lit_by_paths.max(0.1));
+    }
+}
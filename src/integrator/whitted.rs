@@ -0,0 +1,184 @@
+use std::f32::consts::FRAC_1_PI;
+
+use glam::Vec3;
+
+use super::{Integrator, Surface};
+use crate::math::{fresnel_dielectric, Color, Ray};
+use crate::scene::Scene;
+
+// rays that would add less than this to the pixel aren't followed, or a glass
+// ball in front of a mirror would take forever
+const MIN_THROUGHPUT: f32 = 1e-3;
+
+/// Classic recursive ray tracer, splits into perfect reflections and
+/// refractions and only lights diffuse surfaces with the light sources and
+/// the sky straight above them. Fast, but without any indirect light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Whitted {
+    pub max_depth: u32,
+}
+
+impl Integrator for Whitted {
+    fn li(&self, scene: &Scene, ray: Ray) -> Color {
+        self.trace(scene, ray, 0, 1.0)
+    }
+}
+
+impl Whitted {
+    // `throughput` is how much the hit counts towards the pixel
+    fn trace(&self, scene: &Scene, mut ray: Ray, depth: u32, throughput: f32) -> Color {
+        ray.normalize();
+        let Some(hit) = scene.find_closest(ray) else {
+            return scene.sky(ray.dir);
+        };
+        let mat = hit.material;
+        let surface = Surface::new(ray, &hit);
+        let n = surface.n;
+        let bounce = |dir: Vec3, weight: f32| {
+            let throughput = throughput * weight;
+            if depth >= self.max_depth || throughput < MIN_THROUGHPUT {
+                return Color::BLACK;
+            }
+            self.trace(
+                scene,
+                Ray {
+                    pos: surface.p,
+                    dir,
+                    ..ray
+                },
+                depth + 1,
+                throughput,
+            )
+        };
+
+        // the probabilities the path tracer picks lobes with become weights
+        let metal_weight = mat.metalness;
+        let dielectric_weight = mat.transmission * (1.0 - mat.metalness);
+        let metal = if metal_weight > 0.0 {
+            bounce(ray.mirror(n).dir, metal_weight * mat.color.max_channel()) * mat.color
+        } else {
+            Color::BLACK
+        };
+        let dielectric = if dielectric_weight > 0.0 {
+            let eta = if surface.front_face {
+                1.0 / mat.ior
+            } else {
+                mat.ior
+            };
+            match ray.refract(n, eta) {
+                Some(refracted) => {
+                    let reflectance = fresnel_dielectric(ray.dir.dot(n), eta);
+                    let transmitted = (1.0 - reflectance) * mat.color.max_channel();
+                    bounce(ray.mirror(n).dir, dielectric_weight * reflectance) * reflectance
+                        + bounce(refracted.dir, dielectric_weight * transmitted)
+                            * mat.color
+                            * (1.0 - reflectance)
+                }
+                None => bounce(ray.mirror(n).dir, dielectric_weight),
+            }
+        } else {
+            Color::BLACK
+        };
+        let diffuse =
+            (direct_light(scene, surface.p, n, ray.time) * FRAC_1_PI + scene.sky(n)) * mat.color;
+
+        mat.emission
+            + metal * metal_weight
+            + dielectric * dielectric_weight
+            + diffuse * (1.0 - mat.transmission) * (1.0 - mat.metalness)
+    }
+}
+
+// one shadow ray to every light, area lights only get a single sample
+fn direct_light(scene: &Scene, p: Vec3, n: Vec3, time: f32) -> Color {
+    scene.lights.iter().fold(Color::BLACK, |acc, light| {
+        let Some(sample) = light.sample(p) else {
+            return acc;
+        };
+        let cos = sample.dir.dot(n);
+        if cos <= 0.0 || sample.pdf <= 0.0 || !scene.visible(p, sample.dir, sample.dist, time) {
+            return acc;
+        }
+        acc + sample.li * (cos / sample.pdf)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::Whitted;
+    use crate::camera::Camera;
+    use crate::integrator::Integrator;
+    use crate::math::{Color, Material, Plane, Ray, Sphere};
+    use crate::scene::SceneBuilder;
+
+    #[test]
+    fn mirrors_and_glass() {
+        // a glass ball between two mirrors, every ray bounces back and forth
+        // until it runs out of depth and splits at every pass through the ball
+        let blue = Color {
+            r: 0.2,
+            g: 0.4,
+            b: 0.8,
+        };
+        let mirror = Material {
+            color: Color::WHITE,
+            metalness: 1.0,
+            ..Default::default()
+        };
+        let scene = SceneBuilder::new()
+            .object(Plane {
+                pos: Vec3::new(0.0, 0.0, 5.0),
+                norm: -Vec3::Z,
+                material: mirror,
+            })
+            .object(Plane {
+                pos: Vec3::new(0.0, 0.0, -5.0),
+                norm: Vec3::Z,
+                material: mirror,
+            })
+            .object(Sphere {
+                pos: Vec3::new(0.0, 0.0, 2.0),
+                rad: 1.0,
+                material: Material {
+                    color: Color::WHITE,
+                    transmission: 1.0,
+                    ior: 1.5,
+                    ..Default::default()
+                },
+            })
+            .sky_color(blue)
+            .horizon_color(blue)
+            .build(&Camera::default());
+        let whitted = Whitted { max_depth: 40 };
+
+        for i in 0..20 {
+            let ray = Ray {
+                pos: Vec3::ZERO,
+                dir: Vec3::new(i as f32 * 0.01, 0.0, 1.0),
+                ..Default::default()
+            };
+            let color = whitted.li(&scene, ray);
+            assert!(color.r.is_finite() && color.r >= 0.0);
+        }
+
+        // a mirror on its own shows the sky
+        let single = SceneBuilder::new()
+            .object(Plane {
+                pos: Vec3::new(0.0, 0.0, 5.0),
+                norm: -Vec3::Z,
+                material: mirror,
+            })
+            .sky_color(blue)
+            .horizon_color(blue)
+            .build(&Camera::default());
+        let up = Ray {
+            pos: Vec3::ZERO,
+            dir: Vec3::new(0.0, 1.0, 1.0),
+            ..Default::default()
+        };
+        let sky = whitted.li(&single, up);
+        assert!((sky.b - blue.b).abs() < 1e-5, "{sky:?}");
+    }
+}
@@ -0,0 +1,121 @@
+use glam::Vec3;
+
+#[derive(Debug, Clone, Copy)]
+struct KdNode {
+    pos: Vec3,
+    /// index of the point in the slice the tree was built from
+    index: usize,
+    axis: usize,
+}
+
+/// Balanced kd-tree over points, for finding everything close to a spot. Like
+/// the bvh it only hands out indices.
+#[derive(Debug, Clone, Default)]
+pub struct KdTree {
+    // every range of nodes has its splitting node in the middle, the halves
+    // before and after it are the subtrees
+    nodes: Vec<KdNode>,
+}
+
+impl KdTree {
+    pub fn build(points: &[Vec3]) -> Self {
+        let mut nodes: Vec<KdNode> = points
+            .iter()
+            .enumerate()
+            .map(|(index, &pos)| KdNode {
+                pos,
+                index,
+                axis: 0,
+            })
+            .collect();
+        build_range(&mut nodes);
+        Self { nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Calls `found` with the index and squared distance of every point at
+    /// most `radius` away from `center`.
+    pub fn within(&self, center: Vec3, radius: f32, mut found: impl FnMut(usize, f32)) {
+        within_range(&self.nodes, center, radius, &mut found);
+    }
+}
+
+fn build_range(nodes: &mut [KdNode]) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    // split along the axis the points are spread out the most on
+    let (min, max) = nodes.iter().fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(min, max), n| (min.min(n.pos), max.max(n.pos)),
+    );
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |a, b| a.pos[axis].total_cmp(&b.pos[axis]));
+    nodes[mid].axis = axis;
+    let (left, right) = nodes.split_at_mut(mid);
+    build_range(left);
+    build_range(&mut right[1..]);
+}
+
+fn within_range(nodes: &[KdNode], center: Vec3, radius: f32, found: &mut impl FnMut(usize, f32)) {
+    if nodes.is_empty() {
+        return;
+    }
+    let mid = nodes.len() / 2;
+    let node = nodes[mid];
+    let dist_sq = node.pos.distance_squared(center);
+    if dist_sq <= radius * radius {
+        found(node.index, dist_sq);
+    }
+    let d = center[node.axis] - node.pos[node.axis];
+    if d <= radius {
+        within_range(&nodes[..mid], center, radius, found);
+    }
+    if d >= -radius {
+        within_range(&nodes[mid + 1..], center, radius, found);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::KdTree;
+    use crate::math::random_vec;
+
+    #[test]
+    fn matches_brute_force() {
+        let points: Vec<Vec3> = (0..1000).map(|_| random_vec(-5.0, 5.0)).collect();
+        let tree = KdTree::build(&points);
+        assert_eq!(tree.len(), points.len());
+
+        for _ in 0..100 {
+            let center = random_vec(-5.0, 5.0);
+            let radius = rand::random::<f32>() * 2.0;
+            let mut brute: Vec<usize> = (0..points.len())
+                .filter(|&i| points[i].distance_squared(center) <= radius * radius)
+                .collect();
+            let mut found = Vec::new();
+            tree.within(center, radius, |i, _| found.push(i));
+            brute.sort_unstable();
+            found.sort_unstable();
+            assert_eq!(brute, found);
+        }
+    }
+}
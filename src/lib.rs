@@ -0,0 +1,53 @@
+//! A small path tracer that can show its images right in the terminal.
+//!
+//! ```no_run
+//! use glam::Vec3;
+//! use term_rend_rt::{Camera, Material, RenderSettings, Renderer, SceneBuilder};
+//! use term_rend_rt::math::Sphere;
+//!
+//! let camera = Camera {
+//!     pos: Vec3::new(0.0, 1.0, 0.0),
+//!     dir: Vec3::Z,
+//!     ..Default::default()
+//! };
+//! let scene = SceneBuilder::new()
+//!     .object(Sphere {
+//!         pos: Vec3::new(0.0, 1.0, 10.0),
+//!         rad: 1.0,
+//!         material: Material::default(),
+//!     })
+//!     .build(&camera);
+//! let image = Renderer::new(RenderSettings::default())
+//!     .render(&scene)
+//!     .to_rgb_image();
+//! image.save("sphere.png").unwrap();
+//! ```
+
+pub mod bvh;
+pub mod camera;
+pub mod framebuffer;
+pub mod integrator;
+pub mod kdtree;
+pub mod light;
+pub mod math;
+pub mod mesh;
+pub mod motion;
+pub mod obj;
+pub mod renderer;
+mod rng;
+pub mod sampling;
+pub mod scene;
+pub mod scene_file;
+pub mod settings;
+pub mod term;
+pub mod tiles;
+
+pub use camera::Camera;
+pub use framebuffer::Framebuffer;
+pub use integrator::{Integrator, IntegratorKind};
+pub use light::Light;
+pub use math::{Color, Hit, Material, Ray, Renderable};
+pub use renderer::Renderer;
+pub use scene::{Scene, SceneBuilder};
+pub use scene_file::{SceneError, SceneFile};
+pub use settings::RenderSettings;
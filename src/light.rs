@@ -0,0 +1,470 @@
+use std::f32::consts::{FRAC_1_PI, PI};
+
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use serde::Deserialize;
+
+use crate::math::{Color, MIN_HIT_DIST};
+use crate::rng::random;
+use crate::sampling::{
+    cosine_hemisphere, cosine_hemisphere_pdf, uniform_cone, uniform_cone_pdf, uniform_sphere,
+    uniform_triangle,
+};
+
+fn default_sun_diameter() -> f32 {
+    // the real sun seen from earth
+    0.53
+}
+
+fn default_color() -> Color {
+    Color::WHITE
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_inner_angle() -> f32 {
+    20.0
+}
+
+fn default_outer_angle() -> f32 {
+    30.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Light {
+    /// Light from infinitely far away, `dir` points towards the sun.
+    Sun {
+        dir: Vec3,
+        /// apparent size in degrees, bigger suns give softer shadows
+        #[serde(default = "default_sun_diameter")]
+        angular_diameter: f32,
+        #[serde(default = "default_color")]
+        color: Color,
+        /// irradiance on a surface facing the sun
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+    /// Infinitely small light shining in all directions.
+    Point {
+        pos: Vec3,
+        #[serde(default = "default_color")]
+        color: Color,
+        /// radiant intensity, falls off with the squared distance
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+    /// Point light limited to a cone around `dir`.
+    Spot {
+        pos: Vec3,
+        dir: Vec3,
+        /// full intensity inside this angle from `dir`, in degrees
+        #[serde(default = "default_inner_angle")]
+        inner_angle: f32,
+        /// no light further than this from `dir`, smooth falloff in between
+        #[serde(default = "default_outer_angle")]
+        outer_angle: f32,
+        #[serde(default = "default_color")]
+        color: Color,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+    /// An emissive object, these are collected from the materials of the
+    /// scene and can't be written in scene files.
+    #[serde(skip_deserializing)]
+    Area { shape: Shape, emission: Color },
+}
+
+/// Surfaces that can be sampled as area lights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Sphere {
+        pos: Vec3,
+        rad: f32,
+    },
+    Tri {
+        a: Vec3,
+        b: Vec3,
+        c: Vec3,
+    },
+    /// parallelogram with the corner `pos` and the edges `u` and `v`
+    Quad {
+        pos: Vec3,
+        u: Vec3,
+        v: Vec3,
+    },
+}
+
+/// A direction towards a light, from the point it was sampled for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSample {
+    pub dir: Vec3,
+    /// how far the light is along `dir`, anything closer casts a shadow
+    pub dist: f32,
+    /// radiance arriving from `dir`
+    pub li: Color,
+    /// probability density of picking `dir`, per solid angle
+    pub pdf: f32,
+    /// whether bsdf sampling can never hit the light, so there is nothing to
+    /// weigh the sample against
+    pub delta: bool,
+}
+
+impl Light {
+    pub fn sun(dir: Vec3) -> Self {
+        Light::Sun {
+            dir,
+            angular_diameter: default_sun_diameter(),
+            color: default_color(),
+            intensity: default_intensity(),
+        }
+    }
+
+    pub fn point(pos: Vec3, color: Color, intensity: f32) -> Self {
+        Light::Point {
+            pos,
+            color,
+            intensity,
+        }
+    }
+
+    pub fn to_homogeneous(&mut self, view_mat: Mat4) {
+        let point = |p: &mut Vec3| *p = (view_mat * Vec4::from((*p, 1.0))).xyz();
+        match self {
+            Light::Sun { dir, .. } => *dir = view_mat.transform_vector3(*dir).normalize(),
+            Light::Point { pos, .. } => point(pos),
+            Light::Spot { pos, dir, .. } => {
+                point(pos);
+                *dir = view_mat.transform_vector3(*dir).normalize();
+            }
+            Light::Area { shape, .. } => match shape {
+                Shape::Sphere { pos, .. } => point(pos),
+                Shape::Tri { a, b, c } => {
+                    point(a);
+                    point(b);
+                    point(c);
+                }
+                Shape::Quad { pos, u, v } => {
+                    point(pos);
+                    *u = view_mat.transform_vector3(*u);
+                    *v = view_mat.transform_vector3(*v);
+                }
+            },
+        }
+    }
+
+    /// Picks a direction from `p` towards the light. `None` if the light
+    /// can't reach `p` at all.
+    pub fn sample(&self, p: Vec3) -> Option<LightSample> {
+        match *self {
+            Light::Sun {
+                dir,
+                angular_diameter,
+                color,
+                intensity,
+            } => {
+                let cos_max = (angular_diameter.to_radians() / 2.0).min(PI / 2.0).cos();
+                let (dir, pdf) = uniform_cone(dir.normalize(), cos_max);
+                Some(LightSample {
+                    dir,
+                    dist: f32::INFINITY,
+                    // spread the irradiance evenly over the disk of the sun
+                    li: color * (intensity * pdf),
+                    pdf,
+                    // the sky doesn't draw the sun, so bounces never find it
+                    delta: true,
+                })
+            }
+            Light::Point {
+                pos,
+                color,
+                intensity,
+            } => point_sample(p, pos, color * intensity),
+            Light::Spot {
+                pos,
+                dir,
+                inner_angle,
+                outer_angle,
+                color,
+                intensity,
+            } => {
+                let mut sample = point_sample(p, pos, color * intensity)?;
+                let falloff = spot_falloff(dir, inner_angle, outer_angle, -sample.dir);
+                if falloff <= 0.0 {
+                    return None;
+                }
+                sample.li = sample.li * falloff;
+                Some(sample)
+            }
+            Light::Area { shape, emission } => {
+                let (point, normal) = shape.sample();
+                let to_light = point - p;
+                let dist = to_light.length();
+                let dir = to_light / dist;
+                // emissive surfaces light up both of their sides
+                let cos_l = normal.dot(dir).abs();
+                if cos_l <= 0.0 || dist <= MIN_HIT_DIST {
+                    return None;
+                }
+                Some(LightSample {
+                    dir,
+                    // stop short of the light itself so it doesn't shadow
+                    dist: dist - MIN_HIT_DIST,
+                    li: emission,
+                    pdf: area_pdf(dist, cos_l, shape.area()),
+                    delta: false,
+                })
+            }
+        }
+    }
+}
+
+/// A point on a light and a direction light leaves it in, used to trace paths
+/// that start at the lights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmissionSample {
+    pub pos: Vec3,
+    /// `None` for lights without a surface
+    pub normal: Option<Vec3>,
+    pub dir: Vec3,
+    /// density of `pos` per area, 1 for point lights
+    pub pdf_pos: f32,
+    /// density of `dir` per solid angle
+    pub pdf_dir: f32,
+}
+
+impl Light {
+    /// `None` for the sun, paths can't start infinitely far away.
+    pub fn sample_emission(&self) -> Option<EmissionSample> {
+        match *self {
+            Light::Sun { .. } => None,
+            Light::Point { pos, .. } => {
+                let (dir, pdf_dir) = uniform_sphere();
+                Some(EmissionSample {
+                    pos,
+                    normal: None,
+                    dir,
+                    pdf_pos: 1.0,
+                    pdf_dir,
+                })
+            }
+            Light::Spot {
+                pos,
+                dir,
+                outer_angle,
+                ..
+            } => {
+                let cos_outer = outer_angle.to_radians().cos();
+                let (dir, pdf_dir) = uniform_cone(dir.normalize(), cos_outer);
+                Some(EmissionSample {
+                    pos,
+                    normal: None,
+                    dir,
+                    pdf_pos: 1.0,
+                    pdf_dir,
+                })
+            }
+            Light::Area { shape, .. } => {
+                let (pos, normal) = shape.sample();
+                // both sides glow, pick one
+                let side = if random::<f32>() < 0.5 {
+                    normal
+                } else {
+                    -normal
+                };
+                let (dir, pdf) = cosine_hemisphere(side);
+                Some(EmissionSample {
+                    pos,
+                    normal: Some(normal),
+                    dir,
+                    pdf_pos: 1.0 / shape.area(),
+                    pdf_dir: pdf * 0.5,
+                })
+            }
+        }
+    }
+
+    /// Light leaving towards `dir`, radiance for area lights and intensity
+    /// for point lights. `normal` is the one of the emitting point.
+    pub fn emitted(&self, normal: Option<Vec3>, dir: Vec3) -> Color {
+        match *self {
+            Light::Sun { .. } => Color::BLACK,
+            Light::Point {
+                color, intensity, ..
+            } => color * intensity,
+            Light::Spot {
+                dir: spot_dir,
+                inner_angle,
+                outer_angle,
+                color,
+                intensity,
+                ..
+            } => color * (intensity * spot_falloff(spot_dir, inner_angle, outer_angle, dir)),
+            Light::Area { emission, .. } => match normal {
+                Some(n) if n.dot(dir) != 0.0 => emission,
+                _ => Color::BLACK,
+            },
+        }
+    }
+
+    /// Density `sample_emission` picks `dir` with, per solid angle.
+    pub fn emission_pdf(&self, normal: Option<Vec3>, dir: Vec3) -> f32 {
+        match *self {
+            Light::Sun { .. } => 0.0,
+            Light::Point { .. } => 0.25 * FRAC_1_PI,
+            Light::Spot {
+                dir: spot_dir,
+                outer_angle,
+                ..
+            } => {
+                let cos_outer = outer_angle.to_radians().cos();
+                if spot_dir.normalize().dot(dir) < cos_outer {
+                    return 0.0;
+                }
+                uniform_cone_pdf(cos_outer)
+            }
+            Light::Area { .. } => {
+                normal.map_or(0.0, |n| 0.5 * cosine_hemisphere_pdf(n.dot(dir).abs()))
+            }
+        }
+    }
+
+    /// Whether the light sits in a single point, nothing can hit it then.
+    pub fn is_delta_position(&self) -> bool {
+        matches!(self, Light::Point { .. } | Light::Spot { .. })
+    }
+}
+
+impl Shape {
+    pub fn area(&self) -> f32 {
+        match *self {
+            Shape::Sphere { rad, .. } => 4.0 * PI * rad * rad,
+            Shape::Tri { a, b, c } => (b - a).cross(c - a).length() / 2.0,
+            Shape::Quad { u, v, .. } => u.cross(v).length(),
+        }
+    }
+
+    /// Uniformly distributed point on the surface and the normal there.
+    pub fn sample(&self) -> (Vec3, Vec3) {
+        match *self {
+            Shape::Sphere { pos, rad } => {
+                let (n, _) = uniform_sphere();
+                (pos + n * rad, n)
+            }
+            Shape::Tri { a, b, c } => (
+                uniform_triangle(a, b, c).0,
+                (b - a).cross(c - a).normalize(),
+            ),
+            Shape::Quad { pos, u, v } => (
+                pos + u * random::<f32>() + v * random::<f32>(),
+                u.cross(v).normalize(),
+            ),
+        }
+    }
+}
+
+/// Converts the density of uniformly picking a point on a surface of `area`
+/// to a density per solid angle as seen from `dist` away.
+pub fn area_pdf(dist: f32, cos_l: f32, area: f32) -> f32 {
+    dist * dist / (cos_l * area)
+}
+
+/// Weight of a sample taken with density `pdf_a` when `pdf_b` could have
+/// produced it as well.
+pub fn power_heuristic(pdf_a: f32, pdf_b: f32) -> f32 {
+    let (a, b) = (pdf_a * pdf_a, pdf_b * pdf_b);
+    if a + b <= 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+fn point_sample(p: Vec3, pos: Vec3, intensity: Color) -> Option<LightSample> {
+    let to_light = pos - p;
+    let dist_sq = to_light.length_squared();
+    if dist_sq <= 0.0 {
+        return None;
+    }
+    let dist = dist_sq.sqrt();
+    Some(LightSample {
+        dir: to_light / dist,
+        dist,
+        li: intensity * (1.0 / dist_sq),
+        pdf: 1.0,
+        delta: true,
+    })
+}
+
+// how much of a spot light's intensity goes towards `to_point`
+fn spot_falloff(spot_dir: Vec3, inner_angle: f32, outer_angle: f32, to_point: Vec3) -> f32 {
+    let cos_inner = inner_angle.to_radians().cos();
+    let cos_outer = outer_angle.to_radians().cos();
+    smoothstep(cos_outer, cos_inner, to_point.dot(spot_dir))
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x >= edge0 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::{power_heuristic, Light, Shape};
+    use crate::math::Color;
+
+    #[test]
+    fn power_heuristic_weights_sum_to_one() {
+        let (a, b) = (0.3, 1.7);
+        assert!((power_heuristic(a, b) + power_heuristic(b, a) - 1.0).abs() < 1e-6);
+        assert_eq!(power_heuristic(1.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn spot_light_is_dark_outside_its_cone() {
+        let spot = Light::Spot {
+            pos: Vec3::new(0.0, 2.0, 0.0),
+            dir: -Vec3::Y,
+            inner_angle: 20.0,
+            outer_angle: 30.0,
+            color: Color::WHITE,
+            intensity: 4.0,
+        };
+        let below = spot.sample(Vec3::ZERO).unwrap();
+        assert!((below.li.r - 1.0).abs() < 1e-6);
+        assert!(spot.sample(Vec3::new(2.0, 0.0, 0.0)).is_none());
+
+        // the angles are measured from the middle of the cone
+        let edge = |angle: f32| Vec3::new(2.0 * angle.to_radians().tan(), 0.0, 0.0);
+        let inner = spot.sample(edge(20.0)).unwrap();
+        let to_light = inner.dir * inner.dist;
+        let full = 4.0 / to_light.length_squared();
+        assert!(
+            (inner.li.r - full).abs() < 1e-4 * full,
+            "{} {full}",
+            inner.li.r
+        );
+        assert!(spot.sample(edge(25.0)).is_some());
+        assert!(spot.sample(edge(30.5)).is_none());
+    }
+
+    #[test]
+    fn quad_samples_stay_on_the_quad() {
+        let quad = Shape::Quad {
+            pos: Vec3::ZERO,
+            u: Vec3::X * 2.0,
+            v: Vec3::Z,
+        };
+        assert_eq!(quad.area(), 2.0);
+        for _ in 0..100 {
+            let (p, n) = quad.sample();
+            assert!((0.0..=2.0).contains(&p.x) && (0.0..=1.0).contains(&p.z));
+            assert_eq!(p.y, 0.0);
+            assert!(n.abs_diff_eq(-Vec3::Y, 1e-6));
+        }
+    }
+}
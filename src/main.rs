@@ -1,120 +1,163 @@
-use glam::{Mat4, Vec3};
-use math::{random_vec_in_hemisphere, Camera, Color, Material};
+use std::error::Error;
+use std::path::{Path, PathBuf};
 
-use crate::math::{Ray, Renderable};
+use clap::Parser;
+use image::{ImageFormat, RgbImage};
 use show_image::create_window;
+use term_rend_rt::camera::Projection;
+use term_rend_rt::scene_file::{ObjectDesc, SceneFile};
+use term_rend_rt::term::{self, TermSize};
+use term_rend_rt::Renderer;
 
-mod math;
+use crate::cli::{Cli, Command, DisplayMode, RenderArgs};
 
-// the following are options
-const SCREEN_HEIGHT: u32 = 1080;
-const SCREEN_WIDTH: u32 = 1920;
-const SUN_DIR: Vec3 = Vec3::new(0.1, 1.0, 0.3);
-const BOUNCE_AMOUNT: u32 = 70;
-const SAMPLES_PER_PIXEL: u32 = 100;
-const SKY_COL: Color = Color {
-    r: 0.5,
-    g: 0.7,
-    b: 1.0,
-};
+mod cli;
 
-// the following are not to be tweaked
-const PIXEL_SIZE: f32 = 1.0 / SCREEN_WIDTH as f32;
-const PIXEL_OFF_HEIGHT: f32 = PIXEL_SIZE * (SCREEN_HEIGHT as f32 / 2.0);
+const DEFAULT_OUTPUT: &str = "rendered_image.png";
 
-type Scene = Vec<Box<dyn Renderable>>;
+fn main() -> Result<(), Box<dyn Error>> {
+    match Cli::parse().command {
+        Command::Render(args) => run(args, false),
+        Command::Preview(args) => run(args, true),
+        Command::Info { scene } => {
+            print_info(&scene, &load_or_exit(&scene));
+            Ok(())
+        }
+    }
+}
 
-#[show_image::main]
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut tri = math::Tri {
-        a: Vec3::new(0.0, 1.0, 1.5),
-        b: Vec3::new(0.5, 0.0, 1.5),
-        c: Vec3::new(-0.5, 0.0, 1.5),
-        material: Material {
-            color: Color {
-                r: 0.5,
-                g: 0.0,
-                b: 0.5,
-            },
-            metalness: 0.2,
-        },
-    };
+fn load_or_exit(path: &Path) -> SceneFile {
+    SceneFile::load(path).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    })
+}
 
-    let mut sphere = math::Sphere {
-        pos: Vec3::new(0.0, 1.0, 10.0),
-        rad: 1.0,
-        material: Material {
-            color: Color {
-                r: 1.0,
-                g: 1.0,
-                b: 0.0,
-            },
-            metalness: 0.5,
-        },
-    };
+fn run(args: RenderArgs, preview: bool) -> Result<(), Box<dyn Error>> {
+    let file = load_or_exit(&args.scene);
+    let scene = file.build().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    let mut settings = file.settings;
+    if preview {
+        args.apply_preview(&mut settings);
+    } else {
+        args.apply(&mut settings);
+    }
 
-    let mut plane = math::Plane {
-        pos: Vec3::new(0.0, 0.0, 0.0),
-        norm: Vec3::new(0.0, 1.0, 0.0),
-        material: Material {
-            color: Color {
-                r: 1.0,
-                g: 0.0,
-                b: 0.0,
-            },
-            metalness: 0.0,
-        },
+    let renderer = Renderer {
+        settings,
+        threads: args.threads,
     };
-
-    let camera = Camera {
-        pos: Vec3::new(0.0, 1.0, 0.0),
-        dir: Vec3::new(0.0, 0.0, 1.0),
+    let t_start = std::time::Instant::now();
+    let img = renderer
+        .render_with_progress(&scene, |done, total| {
+            eprint!("\r{:.0}% done", (done as f32 / total as f32) * 100.0);
+        })
+        .to_rgb_image();
+    eprintln!("\nit took {:?} to render", t_start.elapsed());
+
+    // previews are only saved when asked to
+    let output = args
+        .output
+        .or_else(|| (!preview).then(|| PathBuf::from(DEFAULT_OUTPUT)));
+    let format = args.format;
+    let default_display = if preview {
+        DisplayMode::Auto
+    } else {
+        DisplayMode::Headless
     };
 
-    let view_matrix = Mat4::look_to_lh(camera.pos, camera.dir, Vec3::Y);
-
-    tri.to_homogeneous(view_matrix);
-    sphere.to_homogeneous(view_matrix);
-    plane.to_homogeneous(view_matrix);
-
-    use image::{Rgb, RgbImage};
+    match args.display.unwrap_or(default_display).resolve() {
+        DisplayMode::Terminal => {
+            // output might not be a tty, e.g. when piped into a file
+            let size = TermSize::query().unwrap_or_default();
+            let protocol = term::detect_protocol();
+            term::print_image(&img, protocol, size, &mut std::io::stdout().lock())?;
+            save(&img, output.as_deref(), format)?;
+            Ok(())
+        }
+        // the window event loop has to own the main thread and never returns
+        DisplayMode::Window | DisplayMode::Auto => show_image::run_context(move || {
+            show_window(&img)?;
+            save(&img, output.as_deref(), format)
+        }),
+        DisplayMode::Headless => {
+            save(&img, output.as_deref(), format)?;
+            Ok(())
+        }
+    }
+}
 
-    let scene: Scene = vec![Box::new(sphere), Box::new(plane)];
+fn save(
+    img: &RgbImage,
+    path: Option<&Path>,
+    format: Option<ImageFormat>,
+) -> Result<(), Box<dyn Error>> {
+    match (path, format) {
+        (Some(path), Some(format)) => img.save_with_format(path, format)?,
+        (Some(path), None) => img.save(path)?,
+        (None, _) => {}
+    }
+    Ok(())
+}
 
-    let mut img = RgbImage::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+fn print_info(path: &Path, file: &SceneFile) {
+    let s = &file.settings;
+    println!("scene:     {}", path.display());
+    println!("size:      {}x{}", s.width, s.height);
+    println!("samples:   {} per pixel", s.samples_per_pixel);
+    println!(
+        "bounces:   {} ({} diffuse, {} specular, {} transmission)",
+        s.max_bounces, s.max_diffuse_bounces, s.max_specular_bounces, s.max_transmission_bounces
+    );
+    println!("tiles:     {}px, {:?} order", s.tile_size, s.tile_order);
+    println!("integrator: {:?}", s.integrator);
+    let camera = &file.camera;
+    match camera.look_at {
+        Some(target) => println!("camera:    at {} looking at {}", camera.pos, target),
+        None => println!("camera:    at {} looking along {}", camera.pos, camera.dir),
+    }
+    match camera.projection {
+        Projection::Perspective => println!("fov:       {:?}", camera.fov),
+        projection => println!("projection: {projection:?}"),
+    }
+    if let Some(stereo) = camera.stereo {
+        println!("stereo:    {stereo:?}");
+    }
+    if camera.aperture > 0.0 {
+        println!(
+            "lens:      {} aperture, focused at {}",
+            camera.aperture,
+            camera.focus_distance()
+        );
+    }
 
-    let t_start = std::time::Instant::now();
-    for y in 0..SCREEN_HEIGHT {
-        println!("{}% done", (y as f32 / SCREEN_HEIGHT as f32) * 100.0);
-        for x in 0..SCREEN_WIDTH {
-            let mut pixel_col = SKY_COL;
-            for s in 0..SAMPLES_PER_PIXEL {
-                let r = Ray {
-                    pos: Vec3::ZERO,
-                    dir: Vec3::new(
-                        -0.5 + (PIXEL_SIZE * x as f32) + rand::random::<f32>() * PIXEL_SIZE,
-                        PIXEL_OFF_HEIGHT - (PIXEL_SIZE * y as f32)
-                            + rand::random::<f32>() * PIXEL_SIZE,
-                        1.0,
-                    ),
-                };
-                pixel_col = pixel_col + cast_ray_recursive(&scene, r, 0);
-            }
-            let ratio = 1.0 / SAMPLES_PER_PIXEL as f32;
-            pixel_col = pixel_col * ratio;
-            img.put_pixel(
-                x,
-                y,
-                Rgb([
-                    (255.0 * pixel_col.r.sqrt()) as u8,
-                    (255.0 * pixel_col.g.sqrt()) as u8,
-                    (255.0 * pixel_col.b.sqrt()) as u8,
-                ]),
-            );
-        }
+    let count = |f: fn(&ObjectDesc) -> bool| file.objects.iter().filter(|o| f(o)).count();
+    let moving = count(|o| matches!(o, ObjectDesc::Moving { .. }));
+    if moving > 0 || !camera.motion.is_static() {
+        let (open, close) = camera.shutter;
+        println!("shutter:   open from {open} to {close}");
     }
-    println!("it took {:?} to render", t_start.elapsed());
+    println!(
+        "objects:   {} ({} spheres, {} planes, {} triangles, {} quads, {} meshes, {} models, {} moving)",
+        file.objects.len(),
+        count(|o| matches!(o, ObjectDesc::Sphere { .. })),
+        count(|o| matches!(o, ObjectDesc::Plane { .. })),
+        count(|o| matches!(o, ObjectDesc::Tri { .. })),
+        count(|o| matches!(o, ObjectDesc::Quad { .. })),
+        count(|o| matches!(o, ObjectDesc::Mesh { .. })),
+        count(|o| matches!(o, ObjectDesc::Obj { .. })),
+        moving,
+    );
+    let mut materials: Vec<&String> = file.materials.keys().collect();
+    materials.sort();
+    println!("materials: {materials:?}");
+    println!("lights:    {:?}", file.lights);
+}
 
+fn show_window(img: &RgbImage) -> Result<(), Box<dyn Error>> {
     let window = create_window("image", Default::default())?;
     window.set_image("image-001", img.clone())?;
 
@@ -127,41 +170,5 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
     }
-    img.save("rendered_image.png")?;
-
     Ok(())
 }
-
-fn cast_ray_recursive(scene: &Scene, ray: Ray, d: u32) -> Color {
-    if d == BOUNCE_AMOUNT {
-        return Color::BLACK;
-    }
-
-    match find_closest(scene, ray) {
-        Some((t, n, mat)) => {
-            let res_p = ray.pos + ray.dir * t;
-            let target = res_p + n + random_vec_in_hemisphere(n);
-            return cast_ray_recursive(
-                scene,
-                Ray {
-                    pos: res_p,
-                    dir: target - res_p,
-                },
-                d + 1,
-            ) * 0.5;
-        }
-        None => {
-            let unit_dir = ray.dir.normalize();
-            let t = 0.5 * (unit_dir.y + 1.0);
-            return Color::WHITE * (1.0 - t) + SKY_COL * t;
-        }
-    }
-}
-
-fn find_closest(scene: &Scene, ray: Ray) -> Option<(f32, Vec3, Material)> {
-    scene
-        .iter()
-        .filter_map(|i| i.intersect(ray))
-        .filter_map(|i| if i.0 < 0.001 { None } else { Some(i) })
-        .min_by(|a, b| a.0.total_cmp(&b.0))
-}
@@ -1,8 +1,17 @@
-use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use std::f32::consts::PI;
+
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use serde::Deserialize;
+
+use crate::light::{Light, Shape};
+use crate::rng::random;
+use crate::sampling::uniform_sphere;
 
 pub const EPSILON: f32 = 0.0001;
+// hits closer than this are the surface the ray just left
+pub const MIN_HIT_DIST: f32 = 0.001;
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
 pub struct Color {
     pub r: f32,
     pub g: f32,
@@ -32,6 +41,31 @@ impl std::ops::Mul<f32> for Color {
         }
     }
 }
+impl Color {
+    pub fn is_black(&self) -> bool {
+        self.r <= 0.0 && self.g <= 0.0 && self.b <= 0.0
+    }
+
+    pub fn max_channel(&self) -> f32 {
+        self.r.max(self.g).max(self.b)
+    }
+
+    /// How bright the color looks.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+}
+
+impl std::ops::Mul<Color> for Color {
+    type Output = Color;
+    fn mul(self, rhs: Color) -> Self::Output {
+        Self {
+            r: self.r * rhs.r,
+            g: self.g * rhs.g,
+            b: self.b * rhs.b,
+        }
+    }
+}
 impl std::ops::Add<Color> for Color {
     type Output = Color;
     fn add(self, rhs: Color) -> Self::Output {
@@ -43,27 +77,125 @@ impl std::ops::Add<Color> for Color {
     }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Material {
     pub color: Color,
+    /// chance of a ray being reflected like on a mirror instead of scattering
     pub metalness: f32,
+    /// how much mirror reflections get blurred, 0 is a perfect mirror
+    pub roughness: f32,
+    /// chance of a non metallic ray going into the surface like into glass,
+    /// `color` tints the light passing through
+    pub transmission: f32,
+    /// index of refraction for transmissive materials, 1.5 is glass, 1.33 water
+    pub ior: f32,
+    /// light given off by the surface, can go above 1 for bright lights
+    pub emission: Color,
+    /// tells materials apart in debug renders, set when loading a scene file
+    #[serde(skip)]
+    pub id: u32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE * 0.5,
+            metalness: 0.0,
+            roughness: 0.0,
+            transmission: 0.0,
+            ior: 1.5,
+            emission: Color::BLACK,
+            id: 0,
+        }
+    }
 }
 
-pub trait Renderable {
-    fn intersect(&self, ray: Ray) -> Option<(f32, Vec3, Material)>;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    /// distance along the normalized ray direction
+    pub t: f32,
+    /// outward facing for closed objects, not necessarily normalized
+    pub normal: Vec3,
+    pub material: Material,
+    /// surface area of the primitive that was hit, needed to weigh light
+    /// from emissive surfaces against sampling them as lights
+    pub area: f32,
+    /// texture coordinates of the hit point
+    pub uv: Vec2,
+}
+
+pub trait Renderable: Send + Sync {
+    fn intersect(&self, ray: Ray) -> Option<Hit>;
     fn to_homogeneous(&mut self, view_mat: Mat4);
+    /// `None` for primitives that extend infinitely, these can't go in the bvh
+    fn bounds(&self) -> Option<Aabb>;
+    /// Area lights for the emissive parts of the object.
+    fn emitters(&self) -> Vec<Light>;
 }
 
-#[derive(Debug, Default, Clone, Copy)]
-pub struct Camera {
-    pub pos: Vec3,
-    pub dir: Vec3,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Aabb {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(points: &[Vec3]) -> Self {
+        points
+            .iter()
+            .fold(Self::EMPTY, |b, &p| b.union(Aabb { min: p, max: p }))
+    }
+
+    pub fn union(self, other: Aabb) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn grow(self, p: Vec3) -> Self {
+        self.union(Aabb { min: p, max: p })
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        if d.min_element() < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// slab test, returns the distance at which the ray enters the box
+    pub fn hit(&self, ray: Ray, inv_dir: Vec3, t_max: f32) -> Option<f32> {
+        let t0 = (self.min - ray.pos) * inv_dir;
+        let t1 = (self.max - ray.pos) * inv_dir;
+        let t_near = t0.min(t1).max_element().max(0.0);
+        let t_far = t0.max(t1).min_element().min(t_max);
+        if t_near <= t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct Ray {
     pub pos: Vec3,
     pub dir: Vec3,
+    /// when the ray was sent while the shutter was open, moving objects are
+    /// hit where they are at that time
+    pub time: f32,
 }
 
 impl Ray {
@@ -72,7 +204,7 @@ impl Ray {
     }
     pub fn reposition(mut self, t: f32) -> Self {
         self.normalize();
-        self.pos = self.pos + self.dir * t;
+        self.pos += self.dir * t;
         self
     }
 
@@ -83,51 +215,96 @@ impl Ray {
         self.dir = self.dir - 2.0 * (self.dir.dot(normal)) * normal;
         self
     }
+
+    /// Bends the ray into the surface following snell's law, `eta` is the
+    /// ratio of the refractive index the ray comes from over the one it goes
+    /// into. `None` on total internal reflection.
+    pub fn refract(mut self, normal: Vec3, eta: f32) -> Option<Self> {
+        self.normalize();
+        let mut normal = normal.normalize();
+        let mut cos_i = -self.dir.dot(normal);
+        if cos_i < 0.0 {
+            normal = -normal;
+            cos_i = -cos_i;
+        }
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        if sin2_t > 1.0 {
+            return None;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        self.dir = eta * self.dir + (eta * cos_i - cos_t) * normal;
+        Some(self)
+    }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
-pub struct Tri {
-    pub a: Vec3,
-    pub b: Vec3,
-    pub c: Vec3,
-    pub material: Material,
+/// Fraction of unpolarized light that gets reflected at the boundary between
+/// two dielectrics, `cos_i` is the cosine of the angle of incidence and `eta`
+/// the ratio of refractive indices like for `Ray::refract`.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.abs().min(1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_s * r_s + r_p * r_p) / 2.0
 }
 
-impl Renderable for Tri {
-    fn intersect(&self, mut ray: Ray) -> Option<(f32, Vec3, Material)> {
-        ray.dir = ray.dir.normalize();
-        let edge1 = self.b - self.a;
-        let edge2 = self.c - self.a;
+/// Distance along the normalized direction of `ray` to the triangle and the
+/// barycentric coordinates of `b` and `c` where it gets hit.
+pub(crate) fn intersect_triangle(a: Vec3, b: Vec3, c: Vec3, mut ray: Ray) -> Option<(f32, Vec2)> {
+    ray.dir = ray.dir.normalize();
+    let edge1 = b - a;
+    let edge2 = c - a;
 
-        let h = ray.dir.cross(edge2);
-        let a = edge1.dot(h);
+    let h = ray.dir.cross(edge2);
+    let det = edge1.dot(h);
 
-        if a > -EPSILON && a < EPSILON {
-            return None;
-        }
+    if det > -EPSILON && det < EPSILON {
+        return None;
+    }
 
-        let f = 1.0 / a;
-        let s = ray.pos - self.a;
-        let u = f * s.dot(h);
+    let f = 1.0 / det;
+    let s = ray.pos - a;
+    let u = f * s.dot(h);
 
-        if u < 0.0 || u > 1.0 {
-            return None;
-        }
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
 
-        let q = s.cross(edge1);
-        let v = f * ray.dir.dot(q);
+    let q = s.cross(edge1);
+    let v = f * ray.dir.dot(q);
 
-        if v < 0.0 || u + v > 1.0 {
-            return None;
-        }
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
 
-        let t = f * edge2.dot(q);
+    let t = f * edge2.dot(q);
+    (t > EPSILON).then_some((t, Vec2::new(u, v)))
+}
 
-        if t > EPSILON {
-            return Some((t, edge1.cross(edge2), self.material));
-        }
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tri {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+    pub material: Material,
+}
 
-        None
+impl Renderable for Tri {
+    fn intersect(&self, ray: Ray) -> Option<Hit> {
+        let (t, uv) = intersect_triangle(self.a, self.b, self.c, ray)?;
+        let normal = (self.b - self.a).cross(self.c - self.a);
+        Some(Hit {
+            t,
+            area: normal.length() / 2.0,
+            normal,
+            material: self.material,
+            // barycentric coordinates of b and c
+            uv,
+        })
     }
 
     fn to_homogeneous(&mut self, view_mat: Mat4) {
@@ -135,6 +312,26 @@ impl Renderable for Tri {
         self.b = (view_mat * Vec4::from((self.b, 1.0))).xyz();
         self.c = (view_mat * Vec4::from((self.c, 1.0))).xyz();
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let b = Aabb::from_points(&[self.a, self.b, self.c]);
+        // axis aligned triangles would give a flat box
+        Some(Aabb {
+            min: b.min - Vec3::splat(EPSILON),
+            max: b.max + Vec3::splat(EPSILON),
+        })
+    }
+
+    fn emitters(&self) -> Vec<Light> {
+        area_light(
+            Shape::Tri {
+                a: self.a,
+                b: self.b,
+                c: self.c,
+            },
+            self.material,
+        )
+    }
 }
 
 pub struct Sphere {
@@ -144,17 +341,13 @@ pub struct Sphere {
 }
 
 impl Renderable for Sphere {
-    fn intersect(&self, mut ray: Ray) -> Option<(f32, Vec3, Material)> {
+    fn intersect(&self, mut ray: Ray) -> Option<Hit> {
         ray.dir = ray.dir.normalize();
         let l_vec = self.pos - ray.pos;
-        let l_l = l_vec.length();
         let tc = l_vec.dot(ray.dir);
 
-        if tc < 0.0 {
-            return None;
-        }
-
-        let d2 = ((tc * tc) - (l_l * l_l)).abs();
+        // squared distance between the center and the closest point on the ray
+        let d2 = l_vec.length_squared() - tc * tc;
 
         let rad2 = self.rad * self.rad;
         if d2 > rad2 {
@@ -163,16 +356,114 @@ impl Renderable for Sphere {
 
         let t1c = (rad2 - d2).sqrt();
 
-        let t = tc - t1c;
+        // the far hit is where rays that started inside leave the sphere
+        let t = [tc - t1c, tc + t1c]
+            .into_iter()
+            .find(|&t| t >= MIN_HIT_DIST)?;
 
         let p = ray.pos + ray.dir * t;
 
-        Some((t, p - self.pos, self.material))
+        // always points outwards, also for hits from the inside
+        let normal = (p - self.pos) / self.rad;
+        Some(Hit {
+            t,
+            normal,
+            material: self.material,
+            area: 4.0 * PI * self.rad * self.rad,
+            // longitude and latitude
+            uv: Vec2::new(
+                0.5 + normal.x.atan2(normal.z) / (2.0 * PI),
+                0.5 + normal.y.clamp(-1.0, 1.0).asin() / PI,
+            ),
+        })
     }
 
     fn to_homogeneous(&mut self, view_mat: Mat4) {
         self.pos = (view_mat * Vec4::from((self.pos, 1.0))).xyz();
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb {
+            min: self.pos - Vec3::splat(self.rad),
+            max: self.pos + Vec3::splat(self.rad),
+        })
+    }
+
+    fn emitters(&self) -> Vec<Light> {
+        area_light(
+            Shape::Sphere {
+                pos: self.pos,
+                rad: self.rad,
+            },
+            self.material,
+        )
+    }
+}
+
+/// A parallelogram spanned by the edges `u` and `v` from the corner `pos`.
+pub struct Quad {
+    pub pos: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Material,
+}
+
+impl Renderable for Quad {
+    fn intersect(&self, mut ray: Ray) -> Option<Hit> {
+        ray.dir = ray.dir.normalize();
+        let normal = self.u.cross(self.v);
+        let denom = normal.dot(ray.dir);
+        if denom.abs() < EPSILON {
+            return None;
+        }
+        let t = (self.pos - ray.pos).dot(normal) / denom;
+        if t <= EPSILON {
+            return None;
+        }
+
+        // express the hit point in the edges of the quad
+        let offset = ray.pos + ray.dir * t - self.pos;
+        let w = normal / normal.length_squared();
+        let alpha = w.dot(offset.cross(self.v));
+        let beta = w.dot(self.u.cross(offset));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        Some(Hit {
+            t,
+            normal,
+            material: self.material,
+            area: normal.length(),
+            uv: Vec2::new(alpha, beta),
+        })
+    }
+
+    fn to_homogeneous(&mut self, view_mat: Mat4) {
+        self.pos = (view_mat * Vec4::from((self.pos, 1.0))).xyz();
+        self.u = view_mat.transform_vector3(self.u);
+        self.v = view_mat.transform_vector3(self.v);
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let p = self.pos;
+        let b = Aabb::from_points(&[p, p + self.u, p + self.v, p + self.u + self.v]);
+        Some(Aabb {
+            min: b.min - Vec3::splat(EPSILON),
+            max: b.max + Vec3::splat(EPSILON),
+        })
+    }
+
+    fn emitters(&self) -> Vec<Light> {
+        area_light(
+            Shape::Quad {
+                pos: self.pos,
+                u: self.u,
+                v: self.v,
+            },
+            self.material,
+        )
+    }
 }
 
 pub struct Plane {
@@ -182,61 +473,130 @@ pub struct Plane {
 }
 
 impl Renderable for Plane {
-    fn intersect(&self, ray: Ray) -> Option<(f32, Vec3, Material)> {
+    fn intersect(&self, ray: Ray) -> Option<Hit> {
         let denom = self.norm.dot(ray.dir);
         if denom.abs() > EPSILON {
             let t = (self.pos - ray.pos).dot(self.norm) / denom;
             if t >= 0.0 {
-                return Some((t - EPSILON, self.norm, self.material));
+                // repeats every unit along two directions in the plane
+                let (u, v) = self.norm.normalize().any_orthonormal_pair();
+                let offset = ray.pos + ray.dir * t - self.pos;
+                return Some(Hit {
+                    t: t - EPSILON,
+                    normal: self.norm,
+                    material: self.material,
+                    area: f32::INFINITY,
+                    uv: Vec2::new(offset.dot(u).rem_euclid(1.0), offset.dot(v).rem_euclid(1.0)),
+                });
             }
         }
         None
     }
     fn to_homogeneous(&mut self, view_mat: Mat4) {
         self.pos = (view_mat * Vec4::from((self.pos, 1.0))).xyz();
+        self.norm = view_mat.transform_vector3(self.norm);
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+
+    // an infinite plane can't be sampled, emissive planes only light up what
+    // random bounces happen to send their way
+    fn emitters(&self) -> Vec<Light> {
+        Vec::new()
     }
 }
 
+fn area_light(shape: Shape, material: Material) -> Vec<Light> {
+    if material.emission.is_black() {
+        return Vec::new();
+    }
+    vec![Light::Area {
+        shape,
+        emission: material.emission,
+    }]
+}
+
 pub fn random_vec(min: f32, max: f32) -> Vec3 {
     let diff = max - min;
     Vec3 {
-        x: (rand::random::<f32>() * diff) + min,
-        y: (rand::random::<f32>() * diff) + min,
-        z: (rand::random::<f32>() * diff) + min,
+        x: (random::<f32>() * diff) + min,
+        y: (random::<f32>() * diff) + min,
+        z: (random::<f32>() * diff) + min,
     }
 }
 
-pub fn random_vec_in_hemisphere(_normal: Vec3) -> Vec3 {
-    loop {
-        let v = random_vec(-1.0, 1.0);
-        if v.length_squared() >= 1.0 {
-            continue;
-        }
-        return v.normalize();
-    }
+// always takes three random numbers, metropolis hands out the same ones
+// again and a rejection loop might never get a point inside
+pub fn random_vec_in_unit_sphere() -> Vec3 {
+    let (dir, _) = uniform_sphere();
+    dir * random::<f32>().cbrt()
 }
 
 #[cfg(test)]
 mod test {
     use glam::Vec3;
 
-    use super::Ray;
+    use super::{fresnel_dielectric, Material, Ray, Renderable, Sphere, EPSILON};
 
     #[test]
     fn ray_mirroring() {
         let mut ray = Ray {
             pos: Vec3::new(-3.0, 3.0, 0.0),
             dir: Vec3::new(1.0, -1.0, 0.0),
+            ..Default::default()
         };
         ray.normalize();
         let normal = Vec3::new(0.0, 1.0, 0.0);
 
-        assert_eq!(
-            ray.mirror(normal),
-            Ray {
-                pos: Vec3::new(-3.0, 3.0, 0.0),
-                dir: Vec3::new(1.0, -1.0, 0.0),
-            }
-        );
+        let mirrored = ray.mirror(normal);
+        assert_eq!(mirrored.pos, Vec3::new(-3.0, 3.0, 0.0));
+        assert!(mirrored
+            .dir
+            .abs_diff_eq(Vec3::new(1.0, 1.0, 0.0).normalize(), EPSILON));
+    }
+
+    #[test]
+    fn ray_refraction() {
+        let ray = Ray {
+            pos: Vec3::ZERO,
+            dir: Vec3::new(1.0, -1.0, 0.0),
+            ..Default::default()
+        };
+        let normal = Vec3::Y;
+
+        // into glass, sin(45°) / 1.5 = sin(theta_t)
+        let refracted = ray.refract(normal, 1.0 / 1.5).unwrap();
+        let sin_t = refracted.dir.x / refracted.dir.length();
+        assert!((sin_t - 45f32.to_radians().sin() / 1.5).abs() < EPSILON);
+        assert!(refracted.dir.y < 0.0);
+
+        // out of glass at 45° is past the critical angle of ~41.8°
+        assert_eq!(ray.refract(normal, 1.5), None);
+    }
+
+    #[test]
+    fn fresnel_at_normal_incidence() {
+        // ((n1 - n2) / (n1 + n2))^2
+        assert!((fresnel_dielectric(1.0, 1.0 / 1.5) - 0.04).abs() < EPSILON);
+        assert_eq!(fresnel_dielectric(0.5, 1.5), 1.0);
+    }
+
+    #[test]
+    fn sphere_hit_from_inside() {
+        let sphere = Sphere {
+            pos: Vec3::ZERO,
+            rad: 2.0,
+            material: Material::default(),
+        };
+        let ray = Ray {
+            pos: Vec3::ZERO,
+            dir: Vec3::X,
+            ..Default::default()
+        };
+        let hit = sphere.intersect(ray).unwrap();
+        assert!((hit.t - 2.0).abs() < EPSILON);
+        assert!(hit.normal.abs_diff_eq(Vec3::X, EPSILON));
     }
 }
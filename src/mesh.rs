@@ -0,0 +1,263 @@
+use std::sync::OnceLock;
+
+use glam::{Mat4, Vec2, Vec3};
+
+use crate::bvh::Bvh;
+use crate::light::{Light, Shape};
+use crate::math::{
+    intersect_triangle, Aabb, Hit, Material, Ray, Renderable, EPSILON, MIN_HIT_DIST,
+};
+
+/// Triangles that share their vertices and a material, with a bvh of their
+/// own so big models are a single object in the scene.
+pub struct Mesh {
+    positions: Vec<Vec3>,
+    /// one per position, or empty to shade every triangle flat
+    normals: Vec<Vec3>,
+    /// one per position, or empty to use the barycentric coordinates like
+    /// `Tri` does
+    uvs: Vec<Vec2>,
+    /// indices of the three corners of every triangle
+    triangles: Vec<[u32; 3]>,
+    pub material: Material,
+    // built the first time it's needed, after the mesh got moved into place
+    bvh: OnceLock<Bvh>,
+}
+
+impl Mesh {
+    /// The normals get interpolated across the triangles for smooth shading.
+    /// `normals` and `uvs` can be left empty, otherwise they need one entry
+    /// for each position. `None` if they don't or a triangle points past the
+    /// positions.
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<Vec2>,
+        triangles: Vec<[u32; 3]>,
+        material: Material,
+    ) -> Option<Self> {
+        let fits = |len| len == 0 || len == positions.len();
+        if !fits(normals.len())
+            || !fits(uvs.len())
+            || triangles
+                .iter()
+                .flatten()
+                .any(|&i| i as usize >= positions.len())
+        {
+            return None;
+        }
+        Some(Self {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            material,
+            bvh: OnceLock::new(),
+        })
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    fn bvh(&self) -> &Bvh {
+        self.bvh.get_or_init(|| self.build_bvh())
+    }
+
+    fn build_bvh(&self) -> Bvh {
+        let bounds: Vec<Aabb> = (0..self.triangles.len())
+            .map(|i| {
+                let b = Aabb::from_points(&self.corners(i));
+                // axis aligned triangles would give a flat box
+                Aabb {
+                    min: b.min - Vec3::splat(EPSILON),
+                    max: b.max + Vec3::splat(EPSILON),
+                }
+            })
+            .collect();
+        Bvh::build(&bounds)
+    }
+
+    fn corners(&self, triangle: usize) -> [Vec3; 3] {
+        self.triangles[triangle].map(|i| self.positions[i as usize])
+    }
+
+    // blends the values at the corners with the barycentric coordinates of
+    // the second and third one
+    fn interpolate<T>(values: &[T], [a, b, c]: [u32; 3], uv: Vec2) -> T
+    where
+        T: Copy + std::ops::Mul<f32, Output = T> + std::ops::Add<Output = T>,
+    {
+        values[a as usize] * (1.0 - uv.x - uv.y)
+            + values[b as usize] * uv.x
+            + values[c as usize] * uv.y
+    }
+}
+
+impl Renderable for Mesh {
+    fn intersect(&self, ray: Ray) -> Option<Hit> {
+        // hits right at the start have to be skipped here, or they would hide
+        // the ones behind them from the scene
+        let (t, (i, uv)) = self.bvh().closest(ray, |i, ray| {
+            let [a, b, c] = self.corners(i);
+            intersect_triangle(a, b, c, ray)
+                .filter(|&(t, _)| t >= MIN_HIT_DIST)
+                .map(|(t, uv)| (t, (i, uv)))
+        })?;
+
+        let [a, b, c] = self.corners(i);
+        let face = (b - a).cross(c - a);
+        let indices = self.triangles[i];
+        Some(Hit {
+            t,
+            normal: if self.normals.is_empty() {
+                face
+            } else {
+                Self::interpolate(&self.normals, indices, uv)
+            },
+            material: self.material,
+            area: face.length() / 2.0,
+            uv: if self.uvs.is_empty() {
+                uv
+            } else {
+                Self::interpolate(&self.uvs, indices, uv)
+            },
+        })
+    }
+
+    fn to_homogeneous(&mut self, view_mat: Mat4) {
+        let normal_mat = view_mat.inverse().transpose();
+        for p in &mut self.positions {
+            *p = view_mat.transform_point3(*p);
+        }
+        for n in &mut self.normals {
+            *n = normal_mat.transform_vector3(*n);
+        }
+        self.bvh = OnceLock::new();
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bvh().bounds()
+    }
+
+    fn emitters(&self) -> Vec<Light> {
+        if self.material.emission.is_black() {
+            return Vec::new();
+        }
+        (0..self.triangles.len())
+            .map(|i| {
+                let [a, b, c] = self.corners(i);
+                Light::Area {
+                    shape: Shape::Tri { a, b, c },
+                    emission: self.material.emission,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Vec2, Vec3};
+
+    use super::Mesh;
+    use crate::math::{random_vec, Material, Ray, Renderable, Tri};
+
+    // a bumpy grid of triangles in front of the camera
+    fn grid(size: u32) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+        let positions = (0..=size)
+            .flat_map(|y| (0..=size).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let z = 10.0 + (x as f32 * 0.7).sin() + (y as f32 * 1.3).cos();
+                Vec3::new(
+                    x as f32 - size as f32 / 2.0,
+                    y as f32 - size as f32 / 2.0,
+                    z,
+                )
+            })
+            .collect();
+        let row = size + 1;
+        let triangles = (0..size)
+            .flat_map(|y| (0..size).map(move |x| y * row + x))
+            .flat_map(|i| [[i, i + 1, i + row], [i + 1, i + row + 1, i + row]])
+            .collect();
+        (positions, triangles)
+    }
+
+    #[test]
+    fn matches_separate_triangles() {
+        let (positions, triangles) = grid(8);
+        let tris: Vec<Tri> = triangles
+            .iter()
+            .map(|t| Tri {
+                a: positions[t[0] as usize],
+                b: positions[t[1] as usize],
+                c: positions[t[2] as usize],
+                material: Material::default(),
+            })
+            .collect();
+        let mesh = Mesh::new(
+            positions,
+            Vec::new(),
+            Vec::new(),
+            triangles,
+            Material::default(),
+        )
+        .unwrap();
+        assert_eq!(mesh.triangle_count(), 128);
+
+        for _ in 0..500 {
+            let ray = Ray {
+                pos: Vec3::ZERO,
+                dir: (random_vec(-0.5, 0.5) + Vec3::Z).normalize(),
+                ..Default::default()
+            };
+            let brute = tris
+                .iter()
+                .filter_map(|t| t.intersect(ray))
+                .map(|h| h.t)
+                .min_by(f32::total_cmp);
+            assert_eq!(brute, mesh.intersect(ray).map(|h| h.t));
+        }
+    }
+
+    #[test]
+    fn normals_and_uvs_get_interpolated() {
+        // one triangle with its normals tilted away from its center, as if
+        // it was part of a sphere
+        let mesh = Mesh::new(
+            vec![
+                Vec3::new(-1.0, -1.0, 5.0),
+                Vec3::new(1.0, -1.0, 5.0),
+                Vec3::new(0.0, 1.0, 5.0),
+            ],
+            vec![
+                Vec3::new(-1.0, -2.0 / 3.0, -1.0),
+                Vec3::new(1.0, -2.0 / 3.0, -1.0),
+                Vec3::new(0.0, 4.0 / 3.0, -1.0),
+            ],
+            vec![Vec2::ZERO, Vec2::X, Vec2::Y],
+            vec![[0, 1, 2]],
+            Material::default(),
+        )
+        .unwrap();
+        let hit = |x: f32, y: f32| {
+            let ray = Ray {
+                pos: Vec3::new(x, y, 0.0),
+                dir: Vec3::Z,
+                ..Default::default()
+            };
+            mesh.intersect(ray).unwrap()
+        };
+        let corner = hit(0.999, -0.999);
+        assert!(corner
+            .normal
+            .abs_diff_eq(Vec3::new(1.0, -2.0 / 3.0, -1.0), 1e-2));
+        assert!(corner.uv.abs_diff_eq(Vec2::X, 1e-2));
+
+        let middle = hit(0.0, -1.0 / 3.0);
+        assert!(middle.normal.normalize().abs_diff_eq(-Vec3::Z, 1e-4));
+        assert!(middle.uv.abs_diff_eq(Vec2::splat(1.0 / 3.0), 1e-4));
+        assert_eq!(middle.t, 5.0);
+    }
+}
@@ -0,0 +1,282 @@
+use glam::{BVec3, EulerRot, Mat4, Quat, Vec3};
+use serde::Deserialize;
+
+use crate::light::Light;
+use crate::math::{Aabb, Hit, Ray, Renderable};
+
+// steps the bounds of moving objects get sampled at between two keyframes,
+// enough that turning objects don't poke out of the box between them
+const BOUNDS_STEPS: usize = 64;
+
+/// Where something is at `time`, relative to where it was placed.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keyframe {
+    pub time: f32,
+    pub translate: Vec3,
+    /// degrees around the x, y and z axes, turned around y first, then x
+    /// and z last
+    pub rotate: Vec3,
+    pub scale: Vec3,
+}
+
+impl Default for Keyframe {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            translate: Vec3::ZERO,
+            rotate: Vec3::ZERO,
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+impl Keyframe {
+    /// Scales, then turns and then moves.
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation(), self.translate)
+    }
+
+    fn rotation(&self) -> Quat {
+        let [x, y, z] = self.rotate.to_array().map(f32::to_radians);
+        Quat::from_euler(EulerRot::YXZ, y, x, z)
+    }
+}
+
+/// Keyframes sorted by time. In between them the transform gets
+/// interpolated, before the first and after the last it stays put.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(from = "Vec<Keyframe>")]
+pub struct Motion {
+    keyframes: Vec<Keyframe>,
+}
+
+impl From<Vec<Keyframe>> for Motion {
+    fn from(mut keyframes: Vec<Keyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { keyframes }
+    }
+}
+
+impl Motion {
+    pub fn new(keyframes: Vec<Keyframe>) -> Self {
+        keyframes.into()
+    }
+
+    /// Whether it stays in the same place all the time.
+    pub fn is_static(&self) -> bool {
+        self.keyframes.len() < 2
+    }
+
+    /// The transform at `time`.
+    pub fn at(&self, time: f32) -> Mat4 {
+        let (scale, rotation, translate) = self.parts(time);
+        Mat4::from_scale_rotation_translation(scale, rotation, translate)
+    }
+
+    /// The inverse of `at`, without inverting a whole matrix.
+    pub fn inverse_at(&self, time: f32) -> Mat4 {
+        let (scale, rotation, translate) = self.parts(time);
+        Mat4::from_scale(scale.recip())
+            * Mat4::from_quat(rotation.inverse())
+            * Mat4::from_translation(-translate)
+    }
+
+    // scale, rotation and translation at `time`
+    fn parts(&self, time: f32) -> (Vec3, Quat, Vec3) {
+        let i = self.keyframes.partition_point(|k| k.time <= time);
+        match (self.keyframes.get(i.wrapping_sub(1)), self.keyframes.get(i)) {
+            (None, None) => (Vec3::ONE, Quat::IDENTITY, Vec3::ZERO),
+            (Some(k), None) | (None, Some(k)) => (k.scale, k.rotation(), k.translate),
+            (Some(a), Some(b)) => {
+                let s = (time - a.time) / (b.time - a.time);
+                (
+                    a.scale.lerp(b.scale, s),
+                    a.rotation().slerp(b.rotation(), s),
+                    a.translate.lerp(b.translate, s),
+                )
+            }
+        }
+    }
+
+    // times that cover the whole motion closely enough to bound it
+    fn sample_times(&self) -> Vec<f32> {
+        let mut times = vec![self.keyframes.first().map_or(0.0, |k| k.time)];
+        for pair in self.keyframes.windows(2) {
+            let (a, b) = (pair[0].time, pair[1].time);
+            times.extend((1..=BOUNDS_STEPS).map(|i| a + (b - a) * i as f32 / BOUNDS_STEPS as f32));
+        }
+        times
+    }
+}
+
+/// Wraps an object that moves, turns and scales around the center of its
+/// bounds (the origin for unbounded ones) while the shutter is open. Rays
+/// see it where it is at their time.
+///
+/// It can't be sampled as a light, emissive moving objects only light up what
+/// random bounces send their way.
+pub struct Moving {
+    object: Box<dyn Renderable>,
+    motion: Motion,
+    // moves the origin to the point the object turns and scales around
+    pivot: Mat4,
+    view: Mat4,
+    // the inverses of the above, so rays don't have to invert a matrix
+    from_pivot: Mat4,
+    from_view: Mat4,
+    // the whole way from view space to the object if it doesn't move
+    still: Option<Mat4>,
+}
+
+impl Moving {
+    pub fn new(object: Box<dyn Renderable>, motion: Motion) -> Self {
+        let center = object.bounds().map_or(Vec3::ZERO, |b| b.centroid());
+        Self::with_pivot(object, motion, center)
+    }
+
+    /// Like `new`, but turns and scales around `pivot`.
+    pub fn with_pivot(object: Box<dyn Renderable>, motion: Motion, pivot: Vec3) -> Self {
+        let mut moving = Self {
+            object,
+            motion,
+            pivot: Mat4::from_translation(pivot),
+            view: Mat4::IDENTITY,
+            from_pivot: Mat4::from_translation(-pivot),
+            from_view: Mat4::IDENTITY,
+            still: None,
+        };
+        moving.update_still();
+        moving
+    }
+
+    // from the space the object was placed in to view space
+    fn transform(&self, time: f32) -> Mat4 {
+        self.view * self.pivot * self.motion.at(time) * self.from_pivot
+    }
+
+    // the inverse of `transform`
+    fn to_object(&self, time: f32) -> Mat4 {
+        self.still.unwrap_or_else(|| {
+            self.pivot * self.motion.inverse_at(time) * self.from_pivot * self.from_view
+        })
+    }
+
+    fn update_still(&mut self) {
+        self.still = None;
+        if self.motion.is_static() {
+            self.still = Some(self.to_object(0.0));
+        }
+    }
+}
+
+impl Renderable for Moving {
+    fn intersect(&self, ray: Ray) -> Option<Hit> {
+        let to_object = self.to_object(ray.time);
+        let dir = to_object.transform_vector3(ray.dir);
+        let local = Ray {
+            pos: to_object.transform_point3(ray.pos),
+            dir: dir.normalize(),
+            ..ray
+        };
+        let hit = self.object.intersect(local)?;
+        // distances get scaled along with the object
+        Some(Hit {
+            t: hit.t * ray.dir.length() / dir.length(),
+            normal: to_object.transpose().transform_vector3(hit.normal),
+            area: f32::INFINITY,
+            ..hit
+        })
+    }
+
+    // the object stays where it was placed, it gets moved into view space
+    // with the rest of the transform
+    fn to_homogeneous(&mut self, view_mat: Mat4) {
+        self.view = view_mat * self.view;
+        self.from_view = self.view.inverse();
+        self.update_still();
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let b = self.object.bounds()?;
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| {
+                let mask = BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0);
+                Vec3::select(mask, b.max, b.min)
+            })
+            .collect();
+        let bounds = self
+            .motion
+            .sample_times()
+            .into_iter()
+            .fold(Aabb::EMPTY, |bounds, time| {
+                let m = self.transform(time);
+                corners
+                    .iter()
+                    .fold(bounds, |bounds, &c| bounds.grow(m.transform_point3(c)))
+            });
+        let slack = Vec3::splat((bounds.max - bounds.min).length() * 1e-3);
+        Some(Aabb {
+            min: bounds.min - slack,
+            max: bounds.max + slack,
+        })
+    }
+
+    fn emitters(&self) -> Vec<Light> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::{Keyframe, Motion, Moving};
+    use crate::math::{Material, Ray, Renderable, Sphere};
+
+    #[test]
+    fn objects_are_where_they_are_at_the_ray_time() {
+        let sphere = Sphere {
+            pos: Vec3::new(0.0, 0.0, 5.0),
+            rad: 0.5,
+            material: Material::default(),
+        };
+        let mut moving = Moving::new(
+            Box::new(sphere),
+            Motion::new(vec![
+                Keyframe {
+                    time: 1.0,
+                    translate: Vec3::new(2.0, 0.0, 0.0),
+                    scale: Vec3::splat(2.0),
+                    ..Default::default()
+                },
+                Keyframe::default(),
+            ]),
+        );
+        moving.to_homogeneous(glam::Mat4::from_translation(Vec3::Z));
+
+        let ray = |x: f32, time| Ray {
+            pos: Vec3::new(x, 0.0, 0.0),
+            dir: Vec3::Z * 3.0,
+            time,
+        };
+        let start = moving.intersect(ray(0.0, 0.0)).unwrap();
+        assert!((start.t - 5.5).abs() < 1e-4);
+        assert!(moving.intersect(ray(2.0, 0.0)).is_none());
+        assert!(moving.intersect(ray(0.0, 1.0)).is_none());
+        // twice as big at the end, the surface is a radius closer
+        let end = moving.intersect(ray(2.0, 1.0)).unwrap();
+        assert!((end.t - 5.0).abs() < 1e-4);
+        assert!(end.normal.normalize().abs_diff_eq(-Vec3::Z, 1e-4));
+        // halfway there
+        assert!(moving.intersect(ray(1.0, 0.5)).is_some());
+        for time in [0.0, 0.3, 0.5, 1.0] {
+            let product = moving.transform(time) * moving.to_object(time);
+            assert!(product.abs_diff_eq(glam::Mat4::IDENTITY, 1e-5));
+        }
+
+        let bounds = moving.bounds().unwrap();
+        assert!(bounds.min.abs_diff_eq(Vec3::new(-0.5, -1.0, 5.0), 1e-2));
+        assert!(bounds.max.abs_diff_eq(Vec3::new(3.0, 1.0, 7.0), 1e-2));
+    }
+}
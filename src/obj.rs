@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use glam::{Vec2, Vec3};
+
+use crate::math::{Color, Material};
+use crate::mesh::Mesh;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, std::io::Error),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{line}: {message}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+// the materials of an mtl library by name
+type Materials = HashMap<String, Material>;
+
+/// Loads a Wavefront `.obj` file with the `.mtl` material libraries it
+/// names, which are looked for next to it. Every group gets a mesh for each
+/// material it uses. Libraries that can't be read only print a warning.
+///
+/// Polygons with more than three corners get split into a fan of triangles,
+/// so they should be convex. Meshes without normals are shaded flat, corners
+/// that are missing theirs in a mesh that has some get the average of the
+/// faces around them.
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Mesh>, ObjError> {
+    let path = path.as_ref();
+    let source = read(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    parse(&source, path, &mut |name| {
+        let path = dir.join(name);
+        parse_mtl(&read(&path)?, &path)
+    })
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    std::fs::read_to_string(path).map_err(|e| ObjError::Io(path.to_owned(), e))
+}
+
+// the triangles of one group with one material, with their own copy of the
+// vertices they use
+#[derive(Default)]
+struct MeshBuilder {
+    material: Material,
+    positions: Vec<Vec3>,
+    normals: Vec<Option<Vec3>>,
+    uvs: Vec<Option<Vec2>>,
+    triangles: Vec<[u32; 3]>,
+    /// index of the vertex made from the position, uv and normal indices
+    /// in the file
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), u32>,
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, corner: Corner, file: &ObjData) -> u32 {
+        let key = (corner.pos, corner.uv, corner.normal);
+        if let Some(&i) = self.vertices.get(&key) {
+            return i;
+        }
+        self.positions.push(file.positions[corner.pos]);
+        self.uvs.push(corner.uv.map(|i| file.uvs[i]));
+        self.normals.push(corner.normal.map(|i| file.normals[i]));
+        let i = self.positions.len() as u32 - 1;
+        self.vertices.insert(key, i);
+        i
+    }
+
+    fn build(self) -> Option<Mesh> {
+        let normals = if self.normals.iter().all(Option::is_none) {
+            Vec::new()
+        } else {
+            // area weighted face normals for the corners that have none
+            let mut generated = vec![Vec3::ZERO; self.positions.len()];
+            for &[a, b, c] in &self.triangles {
+                let [pa, pb, pc] = [a, b, c].map(|i| self.positions[i as usize]);
+                let face = (pb - pa).cross(pc - pa);
+                for i in [a, b, c] {
+                    generated[i as usize] += face;
+                }
+            }
+            self.normals
+                .iter()
+                .zip(generated)
+                .map(|(n, g)| n.unwrap_or(g.normalize_or_zero()))
+                .collect()
+        };
+        let uvs = if self.uvs.iter().all(Option::is_none) {
+            Vec::new()
+        } else {
+            self.uvs.iter().map(|uv| uv.unwrap_or_default()).collect()
+        };
+        Mesh::new(self.positions, normals, uvs, self.triangles, self.material)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Corner {
+    pos: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+// the vertex data everything in the file shares
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    normals: Vec<Vec3>,
+}
+
+// `load_mtl` reads the material library with the given name
+fn parse(
+    source: &str,
+    path: &Path,
+    load_mtl: &mut dyn FnMut(&str) -> Result<Materials, ObjError>,
+) -> Result<Vec<Mesh>, ObjError> {
+    let mut data = ObjData::default();
+    let mut materials = HashMap::new();
+    // in the order they first show up
+    let mut meshes: Vec<MeshBuilder> = Vec::new();
+    let mut mesh_index: HashMap<(String, String), usize> = HashMap::new();
+    let mut group = String::new();
+    let mut material = String::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let error = |message: String| ObjError::Parse {
+            path: path.to_owned(),
+            line: i + 1,
+            message,
+        };
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let rest: Vec<&str> = words.collect();
+        match keyword {
+            "v" => data.positions.push(parse_vec3(&rest).map_err(error)?),
+            "vn" => data.normals.push(parse_vec3(&rest).map_err(error)?),
+            "vt" => {
+                let uv = parse_floats(&rest, 1).map_err(error)?;
+                data.uvs
+                    .push(Vec2::new(uv[0], uv.get(1).copied().unwrap_or(0.0)));
+            }
+            "f" => {
+                if rest.len() < 3 {
+                    return Err(error("faces need at least 3 corners".to_owned()));
+                }
+                let corners = rest
+                    .iter()
+                    .map(|c| parse_corner(c, &data))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(error)?;
+                let key = (group.clone(), material.clone());
+                let index = *mesh_index.entry(key).or_insert_with(|| {
+                    meshes.push(MeshBuilder {
+                        material: materials.get(&material).copied().unwrap_or_default(),
+                        ..Default::default()
+                    });
+                    meshes.len() - 1
+                });
+                let mesh = &mut meshes[index];
+                let vertices: Vec<u32> = corners.iter().map(|&c| mesh.vertex(c, &data)).collect();
+                for j in 1..vertices.len() - 1 {
+                    mesh.triangles
+                        .push([vertices[0], vertices[j], vertices[j + 1]]);
+                }
+            }
+            "g" | "o" => group = rest.join(" "),
+            "usemtl" => material = rest.join(" "),
+            // models often name a library that didn't get shipped with them,
+            // their faces get the default material then
+            "mtllib" => match load_mtl(&rest.join(" ")) {
+                Ok(library) => materials.extend(library),
+                Err(e) => eprintln!("warning: {e}, using the default material"),
+            },
+            // smoothing groups, lines, points and the like
+            _ => {}
+        }
+    }
+
+    Ok(meshes.into_iter().filter_map(MeshBuilder::build).collect())
+}
+
+fn parse_floats(words: &[&str], min: usize) -> Result<Vec<f32>, String> {
+    if words.len() < min {
+        return Err(format!("expected at least {min} numbers"));
+    }
+    words
+        .iter()
+        .map(|w| w.parse().map_err(|_| format!("\"{w}\" is not a number")))
+        .collect()
+}
+
+fn parse_vec3(words: &[&str]) -> Result<Vec3, String> {
+    let v = parse_floats(words, 3)?;
+    Ok(Vec3::new(v[0], v[1], v[2]))
+}
+
+// `v`, `v/vt`, `v//vn` or `v/vt/vn`
+fn parse_corner(word: &str, data: &ObjData) -> Result<Corner, String> {
+    let mut parts = word.split('/');
+    let mut index = |count: usize| -> Result<Option<usize>, String> {
+        match parts.next() {
+            None | Some("") => Ok(None),
+            Some(part) => resolve_index(part, count).map(Some),
+        }
+    };
+    let pos = index(data.positions.len())?.ok_or_else(|| format!("\"{word}\" has no position"))?;
+    let uv = index(data.uvs.len())?;
+    let normal = index(data.normals.len())?;
+    Ok(Corner { pos, uv, normal })
+}
+
+// indices count from 1, negative ones back from the last one read so far
+fn resolve_index(part: &str, count: usize) -> Result<usize, String> {
+    let i: i64 = part
+        .parse()
+        .map_err(|_| format!("\"{part}\" is not an index"))?;
+    let resolved = if i < 0 { count as i64 + i } else { i - 1 };
+    if i == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("index {i} is out of range"));
+    }
+    Ok(resolved as usize)
+}
+
+// the parts of an mtl material that map onto ours
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Color,
+    shininess: f32,
+    dissolve: f32,
+    filter: Color,
+    ior: f32,
+    emission: Color,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Material::default().color,
+            specular: Color::BLACK,
+            shininess: 0.0,
+            dissolve: 1.0,
+            filter: Color::WHITE,
+            ior: Material::default().ior,
+            emission: Color::BLACK,
+        }
+    }
+}
+
+impl MtlMaterial {
+    // the share of the specular color decides how often light bounces off
+    // like off a mirror, shinier surfaces blur those reflections less.
+    // see-through materials get their reflections from the fresnel term
+    // instead, as metal they would never let any light through
+    fn to_material(&self) -> Material {
+        let (kd, ks) = (self.diffuse.max_channel(), self.specular.max_channel());
+        let reflected = self.diffuse + self.specular;
+        let opaque = Color {
+            r: reflected.r.min(1.0),
+            g: reflected.g.min(1.0),
+            b: reflected.b.min(1.0),
+        };
+        Material {
+            color: opaque * self.dissolve + self.filter * (1.0 - self.dissolve),
+            metalness: if self.dissolve >= 1.0 && kd + ks > 0.0 {
+                ks / (kd + ks)
+            } else {
+                0.0
+            },
+            roughness: (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt(),
+            transmission: 1.0 - self.dissolve.clamp(0.0, 1.0),
+            ior: self.ior,
+            emission: self.emission,
+            id: 0,
+        }
+    }
+}
+
+fn parse_mtl(source: &str, path: &Path) -> Result<Materials, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let error = |message: String| ObjError::Parse {
+            path: path.to_owned(),
+            line: i + 1,
+            message,
+        };
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let rest: Vec<&str> = words.collect();
+        if keyword == "newmtl" {
+            if let Some((name, m)) = current.take() {
+                materials.insert(name, m.to_material());
+            }
+            current = Some((rest.join(" "), MtlMaterial::default()));
+            continue;
+        }
+        let Some((_, m)) = &mut current else {
+            continue;
+        };
+        let color = || {
+            parse_vec3(&rest).map(|v| Color {
+                r: v.x,
+                g: v.y,
+                b: v.z,
+            })
+        };
+        let number = || parse_floats(&rest, 1).map(|v| v[0]);
+        match keyword {
+            "Kd" => m.diffuse = color().map_err(error)?,
+            "Ks" => m.specular = color().map_err(error)?,
+            "Ke" => m.emission = color().map_err(error)?,
+            "Tf" => m.filter = color().map_err(error)?,
+            "Ns" => m.shininess = number().map_err(error)?,
+            "Ni" => m.ior = number().map_err(error)?,
+            "d" => m.dissolve = number().map_err(error)?,
+            "Tr" => m.dissolve = 1.0 - number().map_err(error)?,
+            // ambient colors, illumination models and textures
+            _ => {}
+        }
+    }
+    if let Some((name, m)) = current {
+        materials.insert(name, m.to_material());
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use glam::Vec3;
+
+    use super::{parse, parse_mtl, ObjError};
+    use crate::math::{Color, Material, Ray, Renderable};
+    use crate::mesh::Mesh;
+
+    const MTL: &str = "
+newmtl red
+Kd 0.8 0.1 0.1
+Ks 0.2 0.2 0.2
+Ns 198
+newmtl glass # with a comment
+Kd 0 0 0
+Ks 0.05 0.05 0.05
+d 0.1
+Ni 1.45
+newmtl lamp
+Ke 5 5 4
+";
+
+    // a quad with normals in front of the camera and a pentagon without
+    // them behind it, in their own groups
+    const OBJ: &str = "
+mtllib scene.mtl
+v -1 -1 5
+v 1 -1 5
+v 1 1 5
+v -1 1 5
+vn 0 0 -1
+g front
+usemtl red
+f 1//1 2//1 3//1 4//1
+g back
+usemtl glass
+v -2 -2 9
+v 2 -2 9
+v 3 1 9
+v 0 3 9
+v -3 1 9
+f -5 -4 -3 -2 -1
+usemtl lamp
+f 1 2 3
+";
+
+    fn load(obj: &str) -> Result<Vec<Mesh>, ObjError> {
+        parse(obj, Path::new("test.obj"), &mut |name| {
+            assert_eq!(name, "scene.mtl");
+            parse_mtl(MTL, Path::new(name))
+        })
+    }
+
+    #[test]
+    fn maps_mtl_materials() {
+        let materials = parse_mtl(MTL, Path::new("test.mtl")).unwrap();
+        let red = materials["red"];
+        assert_eq!(
+            red.color,
+            Color {
+                r: 1.0,
+                g: 0.3,
+                b: 0.3
+            }
+        );
+        assert!((red.metalness - 0.2).abs() < 1e-6);
+        assert!((red.roughness - 0.1).abs() < 1e-6);
+        let glass = materials["glass"];
+        assert!((glass.transmission - 0.9).abs() < 1e-6);
+        assert_eq!(glass.metalness, 0.0);
+        assert_eq!(glass.ior, 1.45);
+        assert_eq!(
+            materials["lamp"].emission,
+            Color {
+                r: 5.0,
+                g: 5.0,
+                b: 4.0
+            }
+        );
+        assert_eq!(materials.len(), 3);
+    }
+
+    #[test]
+    fn splits_groups_and_materials() {
+        let meshes = load(OBJ).unwrap();
+        let counts: Vec<usize> = meshes.iter().map(|m| m.triangle_count()).collect();
+        assert_eq!(counts, [2, 3, 1]);
+        assert_eq!(meshes[0].material.color.g, 0.3);
+        assert_eq!(meshes[2].material.emission.r, 5.0);
+
+        let ray = |x: f32, y: f32| Ray {
+            pos: Vec3::new(x, y, 0.0),
+            dir: Vec3::Z,
+            ..Default::default()
+        };
+        // the quad has its normal, the pentagon gets the one of its faces
+        let front = meshes[0].intersect(ray(0.5, 0.5)).unwrap();
+        assert_eq!(front.t, 5.0);
+        assert!(front.normal.abs_diff_eq(-Vec3::Z, 1e-5));
+        let back = meshes[1].intersect(ray(-2.5, 1.0)).unwrap();
+        assert_eq!(back.t, 9.0);
+        assert!(back.normal.normalize().abs_diff_eq(Vec3::Z, 1e-5));
+        assert!(meshes[1].intersect(ray(-1.5, -2.5)).is_none());
+    }
+
+    #[test]
+    fn bad_indices_point_at_the_line() {
+        let broken = OBJ.replace("f 1 2 3", "f 1 2 30");
+        match load(&broken) {
+            Err(ObjError::Parse { line, message, .. }) => {
+                assert_eq!(line, 20);
+                assert_eq!(message, "index 30 is out of range");
+            }
+            other => panic!("expected a parse error, got {:?}", other.err()),
+        }
+        assert!(load("f 1 2").is_err());
+    }
+
+    #[test]
+    fn missing_mtl_falls_back_to_the_default() {
+        let obj = OBJ.replace("mtllib scene.mtl", "mtllib lost materials.mtl");
+        let meshes = parse(&obj, Path::new("test.obj"), &mut |name| {
+            assert_eq!(name, "lost materials.mtl");
+            Err(ObjError::Io(
+                name.into(),
+                std::io::ErrorKind::NotFound.into(),
+            ))
+        })
+        .unwrap();
+        assert_eq!(meshes.len(), 3);
+        assert!(meshes.iter().all(|m| m.material == Material::default()));
+    }
+}
@@ -0,0 +1,270 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use glam::{UVec2, Vec2};
+use rayon::prelude::*;
+
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use crate::integrator::{Film, Integrator};
+use crate::math::Color;
+use crate::rng::{self, random};
+use crate::scene::Scene;
+use crate::settings::RenderSettings;
+use crate::tiles::{generate_tiles, Tile};
+
+#[derive(Debug, Clone, Default)]
+pub struct Renderer {
+    pub settings: RenderSettings,
+    /// size of the thread pool to render on, the global rayon pool if `None`
+    pub threads: Option<usize>,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        Self {
+            settings,
+            threads: None,
+        }
+    }
+
+    pub fn render(&self, scene: &Scene) -> Framebuffer {
+        self.render_with_progress(scene, |_, _| {})
+    }
+
+    /// Like `render`, calls `progress` with the number of finished and total
+    /// tiles every time a tile is done.
+    pub fn render_with_progress(
+        &self,
+        scene: &Scene,
+        progress: impl Fn(usize, usize) + Sync,
+    ) -> Framebuffer {
+        let mut integrator = self.settings.integrator.build(&self.settings);
+        self.render_with_integrator(scene, integrator.as_mut(), progress)
+    }
+
+    /// Renders with `integrator` instead of the one from the settings.
+    pub fn render_with_integrator(
+        &self,
+        scene: &Scene,
+        integrator: &mut dyn Integrator,
+        progress: impl Fn(usize, usize) + Sync,
+    ) -> Framebuffer {
+        let pool = self
+            .threads
+            .and_then(|n| rayon::ThreadPoolBuilder::new().num_threads(n).build().ok());
+        match pool {
+            Some(pool) => pool.install(|| self.render_passes(scene, integrator, &progress)),
+            None => self.render_passes(scene, integrator, &progress),
+        }
+    }
+
+    // the samples get split over the passes the integrator wants, their
+    // images are averaged weighted by how many samples each got
+    fn render_passes(
+        &self,
+        scene: &Scene,
+        integrator: &mut dyn Integrator,
+        progress: &(impl Fn(usize, usize) + Sync),
+    ) -> Framebuffer {
+        let settings = &self.settings;
+        let samples = settings.samples_per_pixel.max(1);
+        // every pass needs at least one sample
+        let passes = integrator.passes().clamp(1, samples);
+        let tiles = generate_tiles(
+            settings.width,
+            settings.height,
+            settings.tile_size,
+            settings.tile_order,
+        );
+        let total = tiles.len() * passes as usize;
+        let finished = AtomicUsize::new(0);
+        let camera = self.camera(scene);
+
+        let mut fb = Framebuffer::new(settings.width, settings.height);
+        for pass in 0..passes {
+            if let Some(seed) = settings.seed {
+                rng::seed(pass_seed(seed, pass));
+            }
+            integrator.prepare(scene, pass);
+            // the first passes take what doesn't split evenly
+            let pass_samples = samples / passes + u32::from(pass < samples % passes);
+            let pass = Pass {
+                index: pass,
+                samples: pass_samples,
+                weight: pass_samples as f32 / samples as f32,
+            };
+
+            let done_before = finished.load(Ordering::Relaxed);
+            let film = Film {
+                camera: &camera,
+                samples: pass.samples,
+                // counted in tiles like the tiled passes
+                progress: &|done| {
+                    progress(done_before + (done * tiles.len() as f32) as usize, total);
+                },
+            };
+            match integrator.render_image(scene, &film) {
+                Some(pixels) => {
+                    for (i, pixel) in pixels.into_iter().enumerate() {
+                        let i = i as u32;
+                        let (x, y) = (i % settings.width, i / settings.width);
+                        fb.set(x, y, fb.get(x, y) + pixel * pass.weight);
+                    }
+                    finished.store(done_before + tiles.len(), Ordering::Relaxed);
+                }
+                None => self.render_tiles(scene, &*integrator, &tiles, pass, &mut fb, || {
+                    progress(finished.fetch_add(1, Ordering::Relaxed) + 1, total);
+                }),
+            }
+        }
+        fb
+    }
+
+    fn render_tiles(
+        &self,
+        scene: &Scene,
+        integrator: &dyn Integrator,
+        tiles: &[Tile],
+        pass: Pass,
+        fb: &mut Framebuffer,
+        tile_done: impl Fn() + Sync,
+    ) {
+        // par_bridge hands out the tiles in order so they get started in the
+        // order `tile_order` asks for
+        let rendered: Vec<(Tile, Vec<Color>)> = tiles
+            .iter()
+            .par_bridge()
+            .map(|&tile| {
+                let pixels = self.render_tile(scene, integrator, tile, pass);
+                tile_done();
+                (tile, pixels)
+            })
+            .collect();
+
+        for (tile, pixels) in rendered {
+            for (i, pixel) in pixels.into_iter().enumerate() {
+                let i = i as u32;
+                let (x, y) = (tile.x + i % tile.width, tile.y + i / tile.width);
+                fb.set(x, y, fb.get(x, y) + pixel * pass.weight);
+            }
+        }
+    }
+
+    fn render_tile(
+        &self,
+        scene: &Scene,
+        integrator: &dyn Integrator,
+        tile: Tile,
+        pass: Pass,
+    ) -> Vec<Color> {
+        if let Some(seed) = self.settings.seed {
+            let seed = pass_seed(seed, pass.index);
+            rng::seed(seed ^ ((tile.y as u64) << 32 | tile.x as u64));
+        }
+        let camera = self.camera(scene);
+        let mut pixels = Vec::with_capacity((tile.width * tile.height) as usize);
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                pixels.push(self.render_pixel(
+                    scene,
+                    integrator,
+                    &camera,
+                    UVec2::new(x, y),
+                    pass.samples,
+                ));
+            }
+        }
+        pixels
+    }
+
+    fn render_pixel(
+        &self,
+        scene: &Scene,
+        integrator: &dyn Integrator,
+        camera: &Camera,
+        pixel: UVec2,
+        samples: u32,
+    ) -> Color {
+        let mut pixel_col = Color::BLACK;
+        for _ in 0..samples {
+            let sample = Vec2::new(random::<f32>(), random::<f32>());
+            // parts of the image the projection doesn't cover stay black
+            if let Some(r) = camera.generate_ray(pixel, sample) {
+                pixel_col = pixel_col + integrator.li(scene, r);
+            }
+        }
+        let ratio = 1.0 / samples as f32;
+        pixel_col * ratio
+    }
+
+    // the camera the scene was built for, at the size of the image
+    fn camera(&self, scene: &Scene) -> Camera {
+        Camera {
+            resolution: UVec2::new(self.settings.width, self.settings.height),
+            ..scene.camera.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pass {
+    index: u32,
+    samples: u32,
+    /// share of the pass in the final image
+    weight: f32,
+}
+
+// the first pass keeps the plain seed so single pass renders stay the same
+fn pass_seed(seed: u64, pass: u32) -> u64 {
+    seed.wrapping_add((pass as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::Renderer;
+    use crate::camera::Camera;
+    use crate::integrator::Integrator;
+    use crate::math::{Color, Ray};
+    use crate::scene::{Scene, SceneBuilder};
+    use crate::settings::RenderSettings;
+
+    // counts the samples it gets asked for
+    struct Counter {
+        passes: u32,
+        samples: AtomicUsize,
+    }
+
+    impl Integrator for Counter {
+        fn li(&self, _scene: &Scene, _ray: Ray) -> Color {
+            self.samples.fetch_add(1, Ordering::Relaxed);
+            Color::WHITE
+        }
+
+        fn passes(&self) -> u32 {
+            self.passes
+        }
+    }
+
+    #[test]
+    fn passes_split_the_samples_exactly() {
+        let settings = RenderSettings {
+            width: 4,
+            height: 2,
+            samples_per_pixel: 10,
+            ..Default::default()
+        };
+        let scene = SceneBuilder::new().build(&Camera::default());
+        for passes in [1, 3, 4, 16] {
+            let mut counter = Counter {
+                passes,
+                samples: AtomicUsize::new(0),
+            };
+            let fb =
+                Renderer::new(settings).render_with_integrator(&scene, &mut counter, |_, _| {});
+            assert_eq!(counter.samples.into_inner(), 8 * 10, "{passes} passes");
+            assert!((fb.get(0, 0).r - 1.0).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::cell::RefCell;
+
+use rand::distributions::{Distribution, Standard};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+    // takes over from `RNG` while set, see `with_source`
+    static SOURCE: RefCell<Option<Box<dyn RngCore>>> = RefCell::new(None);
+}
+
+/// Restarts the random sequence of the current thread. Rendering reseeds
+/// per tile so a seeded image doesn't depend on how tiles get scheduled.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Makes `random` on the current thread draw from `source` while `f` runs.
+/// Metropolis uses this to replay and mutate the numbers a path was built
+/// from.
+pub fn with_source<R>(source: Box<dyn RngCore>, f: impl FnOnce() -> R) -> R {
+    let previous = SOURCE.with(|s| s.replace(Some(source)));
+    let result = f();
+    SOURCE.with(|s| *s.borrow_mut() = previous);
+    result
+}
+
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    SOURCE.with(|source| match source.borrow_mut().as_mut() {
+        Some(source) => source.gen(),
+        None => RNG.with(|rng| rng.borrow_mut().gen()),
+    })
+}
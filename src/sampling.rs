@@ -0,0 +1,257 @@
+use std::f32::consts::{FRAC_1_PI, PI};
+
+use glam::{Vec2, Vec3};
+
+use crate::rng::random;
+
+/// Orthonormal basis with `w` along a given normal, used to turn directions
+/// sampled around the z axis into directions around the normal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// `n` has to be normalized.
+    pub fn from_normal(n: Vec3) -> Self {
+        let (u, v) = n.any_orthonormal_pair();
+        Self { u, v, w: n }
+    }
+
+    pub fn to_world(&self, local: Vec3) -> Vec3 {
+        self.u * local.x + self.v * local.y + self.w * local.z
+    }
+
+    pub fn to_local(&self, world: Vec3) -> Vec3 {
+        Vec3::new(world.dot(self.u), world.dot(self.v), world.dot(self.w))
+    }
+}
+
+/// Uniformly distributed direction on the side of the surface `n` points to,
+/// with its pdf per solid angle.
+pub fn uniform_hemisphere(n: Vec3) -> (Vec3, f32) {
+    let cos_theta = random::<f32>();
+    let local = spherical(cos_theta, 2.0 * PI * random::<f32>());
+    (
+        Onb::from_normal(n).to_world(local),
+        uniform_hemisphere_pdf(),
+    )
+}
+
+pub fn uniform_hemisphere_pdf() -> f32 {
+    0.5 * FRAC_1_PI
+}
+
+/// Direction around `n` distributed like the cosine to it, which is what a
+/// lambertian surface reflects.
+pub fn cosine_hemisphere(n: Vec3) -> (Vec3, f32) {
+    // project a point on the unit disk up onto the hemisphere
+    let (d, _) = unit_disk();
+    let z = (1.0 - d.length_squared()).max(0.0).sqrt();
+    let dir = Onb::from_normal(n).to_world(Vec3::new(d.x, d.y, z));
+    (dir, cosine_hemisphere_pdf(z))
+}
+
+/// `cos_theta` is the cosine between the direction and the normal.
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    cos_theta.max(0.0) * FRAC_1_PI
+}
+
+pub fn uniform_sphere() -> (Vec3, f32) {
+    let cos_theta = 1.0 - 2.0 * random::<f32>();
+    (
+        spherical(cos_theta, 2.0 * PI * random::<f32>()),
+        0.25 * FRAC_1_PI,
+    )
+}
+
+/// Uniformly distributed point on the unit disk and its pdf per area.
+pub fn unit_disk() -> (Vec2, f32) {
+    // concentric mapping, keeps neighbouring samples close together unlike
+    // the polar one
+    let o = Vec2::new(random::<f32>(), random::<f32>()) * 2.0 - Vec2::ONE;
+    if o == Vec2::ZERO {
+        return (Vec2::ZERO, FRAC_1_PI);
+    }
+    let (r, theta) = if o.x.abs() > o.y.abs() {
+        (o.x, PI / 4.0 * (o.y / o.x))
+    } else {
+        (o.y, PI / 2.0 - PI / 4.0 * (o.x / o.y))
+    };
+    (Vec2::new(theta.cos(), theta.sin()) * r, FRAC_1_PI)
+}
+
+/// Uniformly distributed direction at most `acos(cos_max)` away from `axis`.
+pub fn uniform_cone(axis: Vec3, cos_max: f32) -> (Vec3, f32) {
+    let cos_theta = 1.0 - random::<f32>() * (1.0 - cos_max);
+    let local = spherical(cos_theta, 2.0 * PI * random::<f32>());
+    (
+        Onb::from_normal(axis).to_world(local),
+        uniform_cone_pdf(cos_max),
+    )
+}
+
+pub fn uniform_cone_pdf(cos_max: f32) -> f32 {
+    1.0 / (2.0 * PI * (1.0 - cos_max))
+}
+
+/// Uniformly distributed point on the triangle and its pdf per area.
+pub fn uniform_triangle(a: Vec3, b: Vec3, c: Vec3) -> (Vec3, f32) {
+    let su = random::<f32>().sqrt();
+    let (s, t) = (1.0 - su, su * random::<f32>());
+    let area = (b - a).cross(c - a).length() / 2.0;
+    (a + (b - a) * t + (c - a) * s, 1.0 / area)
+}
+
+/// Uniformly distributed point in the regular polygon with `sides` corners
+/// on the unit circle, the first one `rotation` radians from the x axis.
+pub fn regular_polygon(sides: u32, rotation: f32) -> Vec2 {
+    // all the triangles from the center to an edge have the same area
+    let side = (random::<f32>() * sides as f32) as u32 % sides;
+    let corner = |i: u32| {
+        let angle = rotation + 2.0 * PI * i as f32 / sides as f32;
+        Vec3::new(angle.cos(), angle.sin(), 0.0)
+    };
+    let (p, _) = uniform_triangle(Vec3::ZERO, corner(side), corner(side + 1));
+    p.truncate()
+}
+
+/// Picks points in the unit square with a density following a grid of
+/// weights, like the brightness of an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution2d {
+    width: usize,
+    /// running sums of the row totals
+    rows: Vec<f32>,
+    /// running sums along each row
+    cells: Vec<f32>,
+}
+
+impl Distribution2d {
+    /// `weights` go row by row, `None` if none of them is above zero.
+    pub fn new(weights: &[f32], width: usize) -> Option<Self> {
+        let mut cells = Vec::with_capacity(weights.len());
+        let mut rows = Vec::with_capacity(weights.len() / width.max(1));
+        let mut total = 0.0;
+        for row in weights.chunks_exact(width.max(1)) {
+            let mut sum = 0.0;
+            for &w in row {
+                sum += w.max(0.0);
+                cells.push(sum);
+            }
+            total += sum;
+            rows.push(total);
+        }
+        (total > 0.0).then_some(Self { width, rows, cells })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Point in the unit square, y counts rows.
+    pub fn sample(&self) -> Vec2 {
+        let pick = random::<f32>() * self.rows[self.rows.len() - 1];
+        let y = self
+            .rows
+            .partition_point(|&r| r <= pick)
+            .min(self.height() - 1);
+        let row = &self.cells[y * self.width..(y + 1) * self.width];
+        let pick = random::<f32>() * row[self.width - 1];
+        let x = row.partition_point(|&c| c <= pick).min(self.width - 1);
+        Vec2::new(
+            (x as f32 + random::<f32>()) / self.width as f32,
+            (y as f32 + random::<f32>()) / self.height() as f32,
+        )
+    }
+}
+
+fn spherical(cos_theta: f32, phi: f32) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use glam::Vec3;
+
+    use super::{
+        cosine_hemisphere, regular_polygon, uniform_cone, uniform_hemisphere, uniform_triangle,
+        Distribution2d, Onb,
+    };
+
+    #[test]
+    fn onb_is_orthonormal() {
+        let n = Vec3::new(0.3, -0.8, 0.5).normalize();
+        let onb = Onb::from_normal(n);
+        let local = Vec3::new(0.2, 0.4, -0.1);
+        assert!(onb.to_local(onb.to_world(local)).abs_diff_eq(local, 1e-5));
+        assert!(onb.u.cross(onb.v).abs_diff_eq(onb.w, 1e-5));
+    }
+
+    #[test]
+    fn hemisphere_samples_follow_the_normal() {
+        let n = Vec3::new(-1.0, 0.2, 0.4).normalize();
+        for _ in 0..1000 {
+            let (dir, pdf) = cosine_hemisphere(n);
+            assert!(dir.dot(n) >= 0.0 && pdf >= 0.0);
+            assert!((dir.length() - 1.0).abs() < 1e-4);
+            let (dir, _) = uniform_hemisphere(n);
+            assert!(dir.dot(n) >= 0.0);
+        }
+    }
+
+    #[test]
+    fn cosine_estimate_integrates_to_pi() {
+        // integral of cos over the hemisphere, estimated with both samplers
+        let n = Vec3::Y;
+        let count = 20000;
+        let (mut cosine, mut uniform) = (0.0, 0.0);
+        for _ in 0..count {
+            let (dir, pdf) = cosine_hemisphere(n);
+            cosine += dir.dot(n) / pdf;
+            let (dir, pdf) = uniform_hemisphere(n);
+            uniform += dir.dot(n) / pdf;
+        }
+        assert!((cosine / count as f32 - PI).abs() < 0.01);
+        assert!((uniform / count as f32 - PI).abs() < 0.1);
+    }
+
+    #[test]
+    fn cone_and_triangle_samples_stay_inside() {
+        let axis = Vec3::Z;
+        for _ in 0..1000 {
+            let (dir, _) = uniform_cone(axis, 0.9);
+            assert!(dir.dot(axis) >= 0.9 - 1e-5);
+
+            let (p, pdf) = uniform_triangle(Vec3::ZERO, Vec3::X, Vec3::Y);
+            assert!(p.x >= 0.0 && p.y >= 0.0 && p.x + p.y <= 1.0 + 1e-5);
+            assert_eq!(pdf, 2.0);
+        }
+    }
+
+    #[test]
+    fn polygon_and_grid_samples_stay_inside() {
+        for _ in 0..1000 {
+            // a square standing on a corner
+            let p = regular_polygon(4, 0.0);
+            assert!(p.x.abs() + p.y.abs() <= 1.0 + 1e-5);
+        }
+
+        // only the top right of a 2x2 grid has any weight
+        let grid = Distribution2d::new(&[0.0, 1.0, 0.0, 0.0], 2).unwrap();
+        for _ in 0..1000 {
+            let p = grid.sample();
+            assert!(p.x >= 0.5 && p.x < 1.0 && p.y >= 0.0 && p.y < 0.5);
+        }
+        assert!(Distribution2d::new(&[0.0; 4], 2).is_none());
+    }
+}
@@ -0,0 +1,161 @@
+use glam::Vec3;
+
+use crate::bvh::Bvh;
+use crate::camera::Camera;
+use crate::light::Light;
+use crate::math::{Aabb, Color, Hit, Ray, Renderable, MIN_HIT_DIST};
+
+pub struct Scene {
+    /// objects with a bounding box, in the order the bvh indexes them
+    bounded: Vec<Box<dyn Renderable>>,
+    bvh: Bvh,
+    /// objects like planes that have to be tested against every ray
+    unbounded: Vec<Box<dyn Renderable>>,
+    /// the sky fades from `horizon_color` to `sky_color` straight up
+    pub sky_color: Color,
+    pub horizon_color: Color,
+    pub lights: Vec<Light>,
+    /// the camera everything was moved in front of
+    pub camera: Camera,
+    /// world up in view space, where the sky is brightest
+    pub up: Vec3,
+}
+
+/// Collects objects and lights in world space, `build` moves them into the
+/// view space of the camera the scene is rendered from.
+pub struct SceneBuilder {
+    objects: Vec<Box<dyn Renderable>>,
+    sky_color: Color,
+    horizon_color: Color,
+    lights: Vec<Light>,
+}
+
+impl Default for SceneBuilder {
+    fn default() -> Self {
+        Self {
+            objects: Vec::new(),
+            sky_color: Scene::DEFAULT_SKY_COL,
+            horizon_color: Color::WHITE,
+            lights: Vec::new(),
+        }
+    }
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn object(mut self, object: impl Renderable + 'static) -> Self {
+        self.objects.push(Box::new(object));
+        self
+    }
+
+    pub fn boxed_object(mut self, object: Box<dyn Renderable>) -> Self {
+        self.objects.push(object);
+        self
+    }
+
+    pub fn sky_color(mut self, color: Color) -> Self {
+        self.sky_color = color;
+        self
+    }
+
+    pub fn horizon_color(mut self, color: Color) -> Self {
+        self.horizon_color = color;
+        self
+    }
+
+    pub fn light(mut self, light: Light) -> Self {
+        self.lights.push(light);
+        self
+    }
+
+    pub fn build(mut self, camera: &Camera) -> Scene {
+        let view_matrix = camera.view_matrix();
+        for object in &mut self.objects {
+            object.to_homogeneous(view_matrix);
+        }
+        for light in &mut self.lights {
+            light.to_homogeneous(view_matrix);
+        }
+        Scene {
+            camera: camera.clone(),
+            up: view_matrix.transform_vector3(Vec3::Y).normalize(),
+            ..Scene::new(
+                self.objects,
+                self.sky_color,
+                self.horizon_color,
+                self.lights,
+            )
+        }
+    }
+}
+
+impl Scene {
+    pub const DEFAULT_SKY_COL: Color = Color {
+        r: 0.5,
+        g: 0.7,
+        b: 1.0,
+    };
+
+    pub fn new(
+        objects: Vec<Box<dyn Renderable>>,
+        sky_color: Color,
+        horizon_color: Color,
+        mut lights: Vec<Light>,
+    ) -> Self {
+        lights.extend(objects.iter().flat_map(|o| o.emitters()));
+        let (bounded, unbounded): (Vec<_>, Vec<_>) =
+            objects.into_iter().partition(|o| o.bounds().is_some());
+        let bounds: Vec<Aabb> = bounded.iter().filter_map(|o| o.bounds()).collect();
+        Self {
+            bvh: Bvh::build(&bounds),
+            bounded,
+            unbounded,
+            sky_color,
+            horizon_color,
+            lights,
+            camera: Camera::default(),
+            up: Vec3::Y,
+        }
+    }
+
+    /// Whether nothing blocks the way from `p` to `dist` along `dir` at
+    /// `time`.
+    pub fn visible(&self, p: Vec3, dir: Vec3, dist: f32, time: f32) -> bool {
+        let ray = Ray {
+            pos: p,
+            dir: dir.normalize(),
+            time,
+        };
+        self.find_closest(ray).is_none_or(|hit| hit.t >= dist)
+    }
+
+    /// Light coming from the sky in direction `dir`.
+    pub fn sky(&self, dir: Vec3) -> Color {
+        let t = 0.5 * (dir.normalize().dot(self.up) + 1.0);
+        self.horizon_color * (1.0 - t) + self.sky_color * t
+    }
+
+    pub fn find_closest(&self, ray: Ray) -> Option<Hit> {
+        let valid = |hit: &Hit| hit.t >= MIN_HIT_DIST;
+
+        let bounded = self
+            .bvh
+            .closest(ray, |i, ray| {
+                self.bounded[i]
+                    .intersect(ray)
+                    .filter(valid)
+                    .map(|hit| (hit.t, hit))
+            })
+            .map(|(_, hit)| hit);
+
+        self.unbounded
+            .iter()
+            .filter_map(|o| o.intersect(ray))
+            .filter(valid)
+            .chain(bounded)
+            .min_by(|a, b| a.t.total_cmp(&b.t))
+    }
+}
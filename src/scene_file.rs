@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use glam::{Vec2, Vec3};
+use ron::extensions::Extensions;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::camera::Camera;
+use crate::light::Light;
+use crate::math::Aabb;
+use crate::math::{Color, Material, Plane, Quad, Renderable, Sphere, Tri};
+use crate::mesh::Mesh;
+use crate::motion::{Keyframe, Motion, Moving};
+use crate::obj::{self, ObjError};
+use crate::scene::{Scene, SceneBuilder};
+use crate::settings::RenderSettings;
+
+fn default_sky_color() -> Color {
+    Scene::DEFAULT_SKY_COL
+}
+
+fn default_horizon_color() -> Color {
+    Color::WHITE
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// Everything needed to render an image, as written in a `.ron` scene file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFile {
+    #[serde(default)]
+    pub settings: RenderSettings,
+    pub camera: Camera,
+    #[serde(default = "default_sky_color")]
+    pub sky_color: Color,
+    #[serde(default = "default_horizon_color")]
+    pub horizon_color: Color,
+    /// named materials that objects can refer to
+    #[serde(default)]
+    pub materials: HashMap<String, Material>,
+    pub objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    pub lights: Vec<Light>,
+    // kept around to point at the right line when a later step fails
+    #[serde(skip)]
+    source: String,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+/// The name of one of the `materials`, or a material written out in place.
+#[derive(Debug, Clone)]
+pub enum MaterialRef {
+    Named(String),
+    Inline(Material),
+}
+
+// an untagged enum would only say that neither matched, this keeps the
+// error of the inline material, like the name of a misspelled field
+impl<'de> Deserialize<'de> for MaterialRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RefVisitor;
+
+        impl<'de> Visitor<'de> for RefVisitor {
+            type Value = MaterialRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a material name or a material")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<MaterialRef, E> {
+                Ok(MaterialRef::Named(name.to_owned()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<MaterialRef, A::Error> {
+                Material::deserialize(MapAccessDeserializer::new(map)).map(MaterialRef::Inline)
+            }
+        }
+
+        deserializer.deserialize_any(RefVisitor)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum ObjectDesc {
+    Sphere {
+        pos: Vec3,
+        rad: f32,
+        material: MaterialRef,
+    },
+    Plane {
+        pos: Vec3,
+        norm: Vec3,
+        material: MaterialRef,
+    },
+    Tri {
+        a: Vec3,
+        b: Vec3,
+        c: Vec3,
+        material: MaterialRef,
+    },
+    /// parallelogram with the corner `pos` and the edges `u` and `v`
+    Quad {
+        pos: Vec3,
+        u: Vec3,
+        v: Vec3,
+        material: MaterialRef,
+    },
+    /// triangles made of the corners `triangles` points at. `normals` and
+    /// `uvs` are optional, one for every position
+    Mesh {
+        positions: Vec<Vec3>,
+        #[serde(default)]
+        normals: Vec<Vec3>,
+        #[serde(default)]
+        uvs: Vec<Vec2>,
+        triangles: Vec<[u32; 3]>,
+        material: MaterialRef,
+    },
+    /// a wavefront `.obj` model with its `.mtl` materials, scaled, turned like
+    /// keyframes do and then moved. `material` replaces all of its materials,
+    /// its own materials all get the same id
+    Obj {
+        path: PathBuf,
+        #[serde(default)]
+        translate: Vec3,
+        #[serde(default)]
+        rotate: Vec3,
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default)]
+        material: Option<MaterialRef>,
+    },
+    /// `object` following the keyframes in `motion` while the shutter is open
+    Moving {
+        object: Box<ObjectDesc>,
+        motion: Motion,
+    },
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, std::io::Error),
+    /// an image the scene uses couldn't be loaded
+    Image(PathBuf, image::ImageError),
+    /// a bokeh image without a single bright pixel, it would let no light
+    /// through
+    EmptyBokeh(PathBuf),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        col: usize,
+        message: String,
+        source_line: String,
+    },
+    UnknownMaterial {
+        path: PathBuf,
+        name: String,
+        line: Option<(usize, String)>,
+    },
+    /// a mesh whose triangles or normals don't fit its positions, `object`
+    /// counts from 0 in the object list
+    InvalidMesh {
+        path: PathBuf,
+        object: usize,
+    },
+    /// an obj model or its materials couldn't be loaded
+    Obj(ObjError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            SceneError::Image(path, e) => write!(f, "{}: {e}", path.display()),
+            SceneError::EmptyBokeh(path) => {
+                write!(f, "{}: the bokeh image is all black", path.display())
+            }
+            SceneError::Parse {
+                path,
+                line,
+                col,
+                message,
+                source_line,
+            } => {
+                writeln!(f, "{}:{line}:{col}: {message}", path.display())?;
+                write_source_line(f, *line, *col, source_line)
+            }
+            SceneError::UnknownMaterial { path, name, line } => match line {
+                Some((line, source_line)) => {
+                    writeln!(f, "{}:{line}: unknown material \"{name}\"", path.display())?;
+                    let col = source_line.find(&format!("\"{name}\"")).unwrap_or(0) + 1;
+                    write_source_line(f, *line, col, source_line)
+                }
+                None => write!(f, "{}: unknown material \"{name}\"", path.display()),
+            },
+            SceneError::InvalidMesh { path, object } => write!(
+                f,
+                "{}: the triangles, normals or uvs of object {object} don't fit its positions",
+                path.display()
+            ),
+            SceneError::Obj(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+fn write_source_line(
+    f: &mut fmt::Formatter<'_>,
+    line: usize,
+    col: usize,
+    src: &str,
+) -> fmt::Result {
+    let gutter = line.to_string().len();
+    writeln!(f, "{line} | {src}")?;
+    write!(f, "{:gutter$} | {:>col$}", "", "^")
+}
+
+impl std::error::Error for SceneError {}
+
+impl SceneFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let source =
+            std::fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_owned(), e))?;
+        Self::parse(&source, path)
+    }
+
+    /// `path` is only used for error messages.
+    pub fn parse(source: &str, path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref().to_owned();
+        // optional fields like the camera target can be written without `Some`
+        let options = ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+        match options.from_str::<SceneFile>(source) {
+            Ok(mut file) => {
+                file.source = source.to_owned();
+                file.path = path;
+                Ok(file)
+            }
+            Err(e) => {
+                let line = e.position.line;
+                Err(SceneError::Parse {
+                    line,
+                    col: e.position.col,
+                    message: e.code.to_string(),
+                    source_line: source_line(source, line).unwrap_or_default(),
+                    path,
+                })
+            }
+        }
+    }
+
+    /// Resolves the materials, loads the images the scene uses and puts the
+    /// objects into view space of the camera.
+    pub fn build(&self) -> Result<Scene, SceneError> {
+        let mut builder = SceneBuilder::new()
+            .sky_color(self.sky_color)
+            .horizon_color(self.horizon_color);
+        for (i, desc) in self.objects.iter().enumerate() {
+            for object in self.object(desc, i)? {
+                builder = builder.boxed_object(object);
+            }
+        }
+        for light in &self.lights {
+            builder = builder.light(*light);
+        }
+
+        // images are looked for next to the scene file
+        let mut camera = self.camera.clone();
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        if let Some(bokeh) = &self.camera.bokeh {
+            camera
+                .load_bokeh(dir)
+                .map_err(|e| SceneError::Image(dir.join(bokeh), e))?;
+            if camera.bokeh_shape.is_none() {
+                return Err(SceneError::EmptyBokeh(dir.join(bokeh)));
+            }
+        }
+        Ok(builder.build(&camera))
+    }
+
+    // `index` is the position in the object list, for inline material ids.
+    // models turn into more than one object
+    fn object(
+        &self,
+        desc: &ObjectDesc,
+        index: usize,
+    ) -> Result<Vec<Box<dyn Renderable>>, SceneError> {
+        let resolve = |m| self.material(m, index);
+        let object: Box<dyn Renderable> = match desc {
+            ObjectDesc::Sphere { pos, rad, material } => Box::new(Sphere {
+                pos: *pos,
+                rad: *rad,
+                material: resolve(material)?,
+            }),
+            ObjectDesc::Plane {
+                pos,
+                norm,
+                material,
+            } => Box::new(Plane {
+                pos: *pos,
+                norm: *norm,
+                material: resolve(material)?,
+            }),
+            ObjectDesc::Tri { a, b, c, material } => Box::new(Tri {
+                a: *a,
+                b: *b,
+                c: *c,
+                material: resolve(material)?,
+            }),
+            ObjectDesc::Quad {
+                pos,
+                u,
+                v,
+                material,
+            } => Box::new(Quad {
+                pos: *pos,
+                u: *u,
+                v: *v,
+                material: resolve(material)?,
+            }),
+            ObjectDesc::Mesh {
+                positions,
+                normals,
+                uvs,
+                triangles,
+                material,
+            } => Box::new(
+                Mesh::new(
+                    positions.clone(),
+                    normals.clone(),
+                    uvs.clone(),
+                    triangles.clone(),
+                    resolve(material)?,
+                )
+                .ok_or_else(|| SceneError::InvalidMesh {
+                    path: self.path.clone(),
+                    object: index,
+                })?,
+            ),
+            ObjectDesc::Obj {
+                path,
+                translate,
+                rotate,
+                scale,
+                material,
+            } => {
+                // next to the scene file like images
+                let dir = self.path.parent().unwrap_or(Path::new(""));
+                let mut meshes = obj::load(dir.join(path)).map_err(SceneError::Obj)?;
+                let placement = Keyframe {
+                    translate: *translate,
+                    rotate: *rotate,
+                    scale: Vec3::splat(*scale),
+                    ..Default::default()
+                };
+                // there's only one inline id per object, so all of the
+                // model's own materials share it and the material id view
+                // shows the model in a single color
+                let inline_id = (self.materials.len() + index) as u32 + 1;
+                for mesh in &mut meshes {
+                    mesh.material = match material {
+                        Some(m) => resolve(m)?,
+                        None => Material {
+                            id: inline_id,
+                            ..mesh.material
+                        },
+                    };
+                    mesh.to_homogeneous(placement.matrix());
+                }
+                return Ok(meshes
+                    .into_iter()
+                    .map(|m| Box::new(m) as Box<dyn Renderable>)
+                    .collect());
+            }
+            ObjectDesc::Moving { object, motion } => {
+                // all parts of a model turn around the same point
+                let parts = self.object(object, index)?;
+                let bounds = parts
+                    .iter()
+                    .filter_map(|p| p.bounds())
+                    .fold(Aabb::EMPTY, Aabb::union);
+                let pivot = if bounds == Aabb::EMPTY {
+                    Vec3::ZERO
+                } else {
+                    bounds.centroid()
+                };
+                return Ok(parts
+                    .into_iter()
+                    .map(|p| {
+                        Box::new(Moving::with_pivot(p, motion.clone(), pivot))
+                            as Box<dyn Renderable>
+                    })
+                    .collect());
+            }
+        };
+        Ok(vec![object])
+    }
+
+    // ids count up through the named materials in alphabetical order, inline
+    // ones come after those and are different for every object
+    fn material(&self, material: &MaterialRef, object: usize) -> Result<Material, SceneError> {
+        match material {
+            MaterialRef::Inline(m) => Ok(Material {
+                id: (self.materials.len() + object) as u32 + 1,
+                ..*m
+            }),
+            MaterialRef::Named(name) => self.named_material(name).ok_or_else(|| {
+                let quoted = format!("\"{name}\"");
+                SceneError::UnknownMaterial {
+                    path: self.path.clone(),
+                    name: name.clone(),
+                    // the first place the name is used as a value is the
+                    // best guess at what the user has to fix
+                    line: self
+                        .source
+                        .lines()
+                        .enumerate()
+                        .find(|(_, l)| l.contains(&quoted) && !l.trim_start().starts_with(&quoted))
+                        .map(|(i, l)| (i + 1, l.to_owned())),
+                }
+            }),
+        }
+    }
+
+    fn named_material(&self, name: &str) -> Option<Material> {
+        let material = self.materials.get(name)?;
+        let id = self.materials.keys().filter(|k| k.as_str() < name).count() as u32 + 1;
+        Some(Material { id, ..*material })
+    }
+}
+
+fn source_line(source: &str, line: usize) -> Option<String> {
+    source.lines().nth(line.checked_sub(1)?).map(str::to_owned)
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::{SceneError, SceneFile};
+
+    const SCENE: &str = r#"(
+    camera: (pos: (0.0, 1.0, 0.0), look_at: (0.0, 1.0, 10.0), fov: Vertical(40.0)),
+    materials: {
+        "red": (color: (r: 1.0, g: 0.0, b: 0.0)),
+    },
+    objects: [
+        Sphere(pos: (0.0, 1.0, 10.0), rad: 1.0, material: "red"),
+        Plane(pos: (0.0, 0.0, 0.0), norm: (0.0, 1.0, 0.0), material: (metalness: 0.5)),
+        Moving(
+            object: Sphere(pos: (2.0, 1.0, 10.0), rad: 0.5, material: "red"),
+            motion: [(time: 1.0, translate: (1.0, 0.0, 0.0)), (time: 0.0)],
+        ),
+        Mesh(
+            positions: [(0.0, 0.0, 8.0), (1.0, 0.0, 8.0), (0.0, 1.0, 8.0), (1.0, 1.0, 8.0)],
+            triangles: [(0, 1, 2), (1, 3, 2)],
+            material: "red",
+        ),
+    ],
+)"#;
+
+    #[test]
+    fn parses_and_builds() {
+        let file = SceneFile::parse(SCENE, "test.ron").unwrap();
+        assert_eq!(file.objects.len(), 4);
+        assert_eq!(file.settings.width, 1920);
+        assert_eq!(file.camera.look_at, Some(Vec3::new(0.0, 1.0, 10.0)));
+        file.build().unwrap();
+    }
+
+    #[test]
+    fn parse_error_points_at_line() {
+        let broken = SCENE.replace("rad: 1.0", "radius: 1.0");
+        match SceneFile::parse(&broken, "test.ron") {
+            Err(SceneError::Parse { line, .. }) => assert_eq!(line, 7),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn inline_material_errors_name_the_field() {
+        let broken = SCENE.replace("(metalness: 0.5)", "(metalnes: 0.5)");
+        match SceneFile::parse(&broken, "test.ron") {
+            Err(SceneError::Parse { line, message, .. }) => {
+                assert_eq!(line, 8);
+                assert!(message.contains("metalnes"), "{message}");
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn black_bokeh_is_an_error() {
+        let dir = std::env::temp_dir().join(format!("trt-bokeh-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        image::GrayImage::new(4, 4)
+            .save(dir.join("black.png"))
+            .unwrap();
+        let scene = SCENE.replace(
+            "fov: Vertical(40.0)",
+            "fov: Vertical(40.0), bokeh: \"black.png\"",
+        );
+        let file = SceneFile::parse(&scene, dir.join("test.ron")).unwrap();
+        let result = file.build();
+        std::fs::remove_dir_all(&dir).unwrap();
+        match result {
+            Err(SceneError::EmptyBokeh(path)) => assert!(path.ends_with("black.png")),
+            Err(e) => panic!("expected an empty bokeh error, got {e:?}"),
+            Ok(_) => panic!("expected an empty bokeh error"),
+        }
+    }
+
+    #[test]
+    fn mesh_indices_get_checked() {
+        let broken = SCENE.replace("(1, 3, 2)", "(1, 4, 2)");
+        let file = SceneFile::parse(&broken, "test.ron").unwrap();
+        match file.build() {
+            Err(SceneError::InvalidMesh { object, .. }) => assert_eq!(object, 3),
+            Err(e) => panic!("expected an invalid mesh error, got {e:?}"),
+            Ok(_) => panic!("expected an invalid mesh error"),
+        }
+    }
+
+    #[test]
+    fn unknown_material_points_at_line() {
+        let broken = SCENE.replace("material: \"red\"", "material: \"blue\"");
+        let file = SceneFile::parse(&broken, "test.ron").unwrap();
+        match file.build() {
+            Err(SceneError::UnknownMaterial { name, line, .. }) => {
+                assert_eq!(name, "blue");
+                assert_eq!(line.map(|l| l.0), Some(7));
+            }
+            Err(e) => panic!("expected an unknown material error, got {e:?}"),
+            Ok(_) => panic!("expected an unknown material error"),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use serde::Deserialize;
+
+use crate::integrator::IntegratorKind;
+use crate::tiles::TileOrder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    /// limit on the length of a path, whatever it bounces off
+    pub max_bounces: u32,
+    /// limits per kind of bounce, a path stops at whichever is hit first
+    pub max_diffuse_bounces: u32,
+    pub max_specular_bounces: u32,
+    pub max_transmission_bounces: u32,
+    /// paths longer than this get randomly cut off when they carry little light
+    pub roulette_depth: u32,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    /// random if not set
+    pub seed: Option<u64>,
+    /// how light gets carried through the scene
+    pub integrator: IntegratorKind,
+    /// how many reflections and refractions the whitted integrator follows,
+    /// every hit on glass splits the ray in two
+    pub whitted_depth: u32,
+    /// how far the ambient occlusion integrator looks for occluders
+    pub ao_distance: f32,
+    /// photons shot by the photon integrator before each pass
+    pub photons_per_pass: usize,
+    /// how far around a point photons are gathered in the first pass, it
+    /// shrinks with every pass
+    pub photon_radius: f32,
+    pub photon_passes: u32,
+    /// random paths the metropolis integrator measures the image brightness
+    /// with and picks the starts of its chains from
+    pub mlt_bootstrap_samples: usize,
+    /// number of independent chains of mutated paths
+    pub mlt_chains: usize,
+    /// chance of a metropolis mutation throwing the path away for a new one
+    pub mlt_large_step: f32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            samples_per_pixel: 100,
+            max_bounces: 70,
+            max_diffuse_bounces: 8,
+            max_specular_bounces: 32,
+            max_transmission_bounces: 32,
+            roulette_depth: 3,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            seed: None,
+            integrator: IntegratorKind::Path,
+            whitted_depth: 6,
+            ao_distance: 1.0,
+            photons_per_pass: 100_000,
+            photon_radius: 0.05,
+            photon_passes: 16,
+            mlt_bootstrap_samples: 100_000,
+            mlt_chains: 1000,
+            mlt_large_step: 0.3,
+        }
+    }
+}
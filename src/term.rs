@@ -0,0 +1,326 @@
+use std::io::{self, IsTerminal, Write};
+
+use image::{imageops::FilterType, ImageOutputFormat, RgbImage};
+
+mod iterm;
+mod kitty;
+mod sixel;
+
+pub use iterm::write_iterm;
+pub use kitty::write_kitty;
+pub use sixel::write_sixel;
+
+const UPPER_HALF_BLOCK: char = '▀';
+// most terminal fonts are about twice as tall as they are wide, used when
+// the terminal doesn't report its size in pixels
+const DEFAULT_CELL_ASPECT: f32 = 2.0;
+const DEFAULT_CELL_WIDTH: u32 = 10;
+// how long to wait for the terminal to answer a query
+#[cfg(unix)]
+const QUERY_TIMEOUT_MS: i32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Iterm,
+    Sixel,
+    /// colored character cells, works in any truecolor terminal
+    HalfBlock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TermSize {
+    pub columns: u32,
+    pub rows: u32,
+    /// height / width of a single character cell
+    pub cell_aspect: f32,
+    /// size of the whole terminal in pixels, 0 if the terminal doesn't say
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+}
+
+impl Default for TermSize {
+    fn default() -> Self {
+        Self {
+            columns: 80,
+            rows: 24,
+            cell_aspect: DEFAULT_CELL_ASPECT,
+            pixel_width: 0,
+            pixel_height: 0,
+        }
+    }
+}
+
+impl TermSize {
+    pub fn query() -> io::Result<Self> {
+        let size = crossterm::terminal::window_size()?;
+        let cell_aspect = if size.width > 0 && size.height > 0 {
+            (size.height as f32 / size.rows as f32) / (size.width as f32 / size.columns as f32)
+        } else {
+            DEFAULT_CELL_ASPECT
+        };
+        Ok(Self {
+            columns: size.columns as u32,
+            rows: size.rows as u32,
+            cell_aspect,
+            pixel_width: size.width as u32,
+            pixel_height: size.height as u32,
+        })
+    }
+
+    /// Largest size in pixels the image can be shown at without scrolling,
+    /// guessed from the cell count if the terminal doesn't report pixels.
+    pub fn fit_pixels(&self, width: u32, height: u32) -> (u32, u32) {
+        let (max_w, max_h) = if self.pixel_width > 0 && self.pixel_height > 0 {
+            let (_, cell_h) = self.cell_pixels();
+            (self.pixel_width, self.pixel_height.saturating_sub(cell_h))
+        } else {
+            let (cell_w, cell_h) = self.cell_pixels();
+            (self.columns * cell_w, self.rows.saturating_sub(1) * cell_h)
+        };
+        let scale = (max_w as f32 / width as f32)
+            .min(max_h as f32 / height as f32)
+            .min(1.0);
+        (
+            ((width as f32 * scale) as u32).max(1),
+            ((height as f32 * scale) as u32).max(1),
+        )
+    }
+
+    /// Columns and rows the image takes up at the size `fit_pixels` picks,
+    /// for protocols that scale the image to a box of cells themselves.
+    pub fn fit_cells(&self, width: u32, height: u32) -> (u32, u32) {
+        let (w, h) = self.fit_pixels(width, height);
+        let (cell_w, cell_h) = self.cell_pixels();
+        (
+            w.div_ceil(cell_w).clamp(1, self.columns.max(1)),
+            h.div_ceil(cell_h)
+                .clamp(1, self.rows.saturating_sub(1).max(1)),
+        )
+    }
+
+    // size of a single character cell in pixels
+    fn cell_pixels(&self) -> (u32, u32) {
+        if self.pixel_width > 0 && self.pixel_height > 0 {
+            (
+                (self.pixel_width / self.columns.max(1)).max(1),
+                (self.pixel_height / self.rows.max(1)).max(1),
+            )
+        } else {
+            let cell_w = DEFAULT_CELL_WIDTH;
+            (cell_w, (cell_w as f32 * self.cell_aspect) as u32)
+        }
+    }
+
+    /// Size in half-block pixels that an image of `width` x `height` should be
+    /// scaled to so it fits the terminal and keeps its aspect ratio. One row
+    /// is left free for the prompt.
+    pub fn fit(&self, width: u32, height: u32) -> (u32, u32) {
+        // every cell shows two pixels on top of each other
+        let pixel_aspect = self.cell_aspect / 2.0;
+        let max_w = self.columns.max(1) as f32;
+        let max_h = (self.rows.saturating_sub(1).max(1) * 2) as f32;
+
+        let mut w = max_w;
+        let mut h = w * (height as f32 / width as f32) / pixel_aspect;
+        if h > max_h {
+            h = max_h;
+            w = h * pixel_aspect * (width as f32 / height as f32);
+        }
+        ((w.round() as u32).max(1), (h.round() as u32).max(1))
+    }
+}
+
+/// Picks the best image protocol the terminal supports. Known terminals are
+/// recognized from the environment, otherwise the terminal is asked whether
+/// it can do sixels. Half blocks if stdout isn't a terminal.
+pub fn detect_protocol() -> Protocol {
+    // escape codes written into a file or a pipe would only be garbage there
+    if !io::stdout().is_terminal() {
+        return Protocol::HalfBlock;
+    }
+    let var = |name| std::env::var(name).unwrap_or_default();
+    let term = var("TERM");
+    let program = var("TERM_PROGRAM");
+
+    if std::env::var_os("KITTY_WINDOW_ID").is_some()
+        || term == "xterm-kitty"
+        || term == "xterm-ghostty"
+        || program == "ghostty"
+    {
+        return Protocol::Kitty;
+    }
+    if program == "iTerm.app" || program == "WezTerm" || var("LC_TERMINAL") == "iTerm2" {
+        return Protocol::Iterm;
+    }
+    if term.contains("sixel") || term.starts_with("foot") || term == "mlterm" {
+        return Protocol::Sixel;
+    }
+    if query_sixel_support() {
+        return Protocol::Sixel;
+    }
+    Protocol::HalfBlock
+}
+
+pub fn print_image(
+    img: &RgbImage,
+    protocol: Protocol,
+    size: TermSize,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match protocol {
+        Protocol::Kitty => {
+            let (columns, rows) = size.fit_cells(img.width(), img.height());
+            write_kitty(img, columns, rows, out)
+        }
+        Protocol::Iterm => {
+            let (columns, rows) = size.fit_cells(img.width(), img.height());
+            write_iterm(img, columns, rows, out)
+        }
+        Protocol::Sixel => {
+            let (w, h) = size.fit_pixels(img.width(), img.height());
+            let img = image::imageops::resize(img, w, h, FilterType::Triangle);
+            write_sixel(&img, out)
+        }
+        Protocol::HalfBlock => print_half_blocks(img, size, out),
+    }
+}
+
+fn encode_png(img: &RgbImage) -> io::Result<Vec<u8>> {
+    let mut png = io::Cursor::new(Vec::new());
+    img.write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(io::Error::other)?;
+    Ok(png.into_inner())
+}
+
+// sends a primary device attributes request, terminals that can draw sixels
+// include a 4 in their answer
+#[cfg(unix)]
+fn query_sixel_support() -> bool {
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+    if crossterm::terminal::enable_raw_mode().is_err() {
+        return false;
+    }
+    let response = query_primary_attributes();
+    let _ = crossterm::terminal::disable_raw_mode();
+
+    response.is_some_and(|r| {
+        r.trim_start_matches("\x1b[?")
+            .trim_end_matches('c')
+            .split(';')
+            .any(|attr| attr == "4")
+    })
+}
+
+#[cfg(not(unix))]
+fn query_sixel_support() -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn query_primary_attributes() -> Option<String> {
+    let mut stdout = io::stdout();
+    stdout.write_all(b"\x1b[c").ok()?;
+    stdout.flush().ok()?;
+
+    // read byte by byte straight from the fd, the buffered stdin would
+    // swallow the answer, and give up if the terminal doesn't respond
+    let mut response = Vec::new();
+    loop {
+        let mut fds = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut fds, 1, QUERY_TIMEOUT_MS) };
+        if ready <= 0 {
+            return None;
+        }
+        let mut byte = 0u8;
+        let read = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+        if read != 1 {
+            return None;
+        }
+        response.push(byte);
+        if byte == b'c' {
+            return String::from_utf8(response).ok();
+        }
+    }
+}
+
+/// Draws the image into the terminal with 24 bit colors, two pixels per
+/// character cell using the upper half block.
+pub fn print_half_blocks(img: &RgbImage, size: TermSize, out: &mut impl Write) -> io::Result<()> {
+    let (w, h) = size.fit(img.width(), img.height());
+    let img = image::imageops::resize(img, w, h, FilterType::Triangle);
+
+    let mut buf = String::new();
+    for y in (0..h).step_by(2) {
+        let mut last = None;
+        for x in 0..w {
+            let top = img.get_pixel(x, y).0;
+            let bottom = (y + 1 < h).then(|| img.get_pixel(x, y + 1).0);
+            // only emit escape codes when the colors change
+            if last != Some((top, bottom)) {
+                buf.push_str(&format!("\x1b[38;2;{};{};{}m", top[0], top[1], top[2]));
+                match bottom {
+                    Some(b) => buf.push_str(&format!("\x1b[48;2;{};{};{}m", b[0], b[1], b[2])),
+                    None => buf.push_str("\x1b[49m"),
+                }
+                last = Some((top, bottom));
+            }
+            buf.push(UPPER_HALF_BLOCK);
+        }
+        buf.push_str("\x1b[0m\n");
+    }
+    out.write_all(buf.as_bytes())?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::TermSize;
+
+    #[test]
+    fn fit_keeps_aspect_ratio() {
+        let size = TermSize {
+            columns: 200,
+            rows: 51,
+            ..Default::default()
+        };
+        // square pixels, limited by the height
+        assert_eq!(size.fit(1920, 1080), (178, 100));
+        assert_eq!(size.fit(200, 50), (200, 50));
+    }
+
+    #[test]
+    fn fit_pixels_never_upscales() {
+        let size = TermSize {
+            columns: 100,
+            rows: 31,
+            pixel_width: 1000,
+            pixel_height: 620,
+            ..Default::default()
+        };
+        assert_eq!(size.fit_pixels(100, 50), (100, 50));
+        assert_eq!(size.fit_pixels(2000, 1000), (1000, 500));
+        assert_eq!(size.fit_pixels(1000, 1000), (600, 600));
+    }
+
+    #[test]
+    fn fit_cells_leaves_room_for_the_prompt() {
+        let size = TermSize {
+            columns: 100,
+            rows: 31,
+            pixel_width: 1000,
+            pixel_height: 620,
+            ..Default::default()
+        };
+        assert_eq!(size.fit_cells(2000, 1000), (100, 25));
+        // too tall for the width to matter
+        assert_eq!(size.fit_cells(1000, 2000), (30, 30));
+        assert_eq!(size.fit_cells(95, 41), (10, 3));
+    }
+}
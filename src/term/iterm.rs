@@ -0,0 +1,25 @@
+use std::io::{self, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::RgbImage;
+
+use super::encode_png;
+
+/// Sends the image as a png through the iTerm2 inline image protocol,
+/// scaled to fit `columns` x `rows` character cells.
+pub fn write_iterm(
+    img: &RgbImage,
+    columns: u32,
+    rows: u32,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let png = encode_png(img)?;
+    write!(
+        out,
+        "\x1b]1337;File=inline=1;size={};width={columns};height={rows};preserveAspectRatio=1:{}\x07",
+        png.len(),
+        STANDARD.encode(&png)
+    )?;
+    writeln!(out)?;
+    out.flush()
+}
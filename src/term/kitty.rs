@@ -0,0 +1,35 @@
+use std::io::{self, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::RgbImage;
+
+use super::encode_png;
+
+// the protocol limits the payload of a single escape sequence
+const CHUNK_SIZE: usize = 4096;
+
+/// Sends the image as a png through the kitty graphics protocol, scaled to
+/// `columns` x `rows` character cells.
+pub fn write_kitty(
+    img: &RgbImage,
+    columns: u32,
+    rows: u32,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let data = STANDARD.encode(encode_png(img)?);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(CHUNK_SIZE).collect();
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        if i == 0 {
+            // a=T transmits and displays, f=100 is png
+            write!(out, "\x1b_Ga=T,f=100,c={columns},r={rows},m={more};")?;
+        } else {
+            write!(out, "\x1b_Gm={more};")?;
+        }
+        out.write_all(chunk)?;
+        out.write_all(b"\x1b\\")?;
+    }
+    writeln!(out)?;
+    out.flush()
+}
@@ -0,0 +1,92 @@
+use std::io::{self, Write};
+
+use color_quant::NeuQuant;
+use image::RgbImage;
+
+const PALETTE_SIZE: usize = 256;
+// 1 is the slowest but best quality, 30 the fastest
+const QUANT_SAMPLE_FACTOR: i32 = 10;
+
+/// Encodes the image as a DEC sixel sequence with a 256 color palette.
+pub fn write_sixel(img: &RgbImage, out: &mut impl Write) -> io::Result<()> {
+    let (w, h) = img.dimensions();
+    let rgba: Vec<u8> = img.pixels().flat_map(|p| [p[0], p[1], p[2], 255]).collect();
+    let quant = NeuQuant::new(QUANT_SAMPLE_FACTOR, PALETTE_SIZE, &rgba);
+    let indices: Vec<u8> = rgba
+        .chunks_exact(4)
+        .map(|p| quant.index_of(p) as u8)
+        .collect();
+
+    let mut buf = String::new();
+    // P2 = 1 keeps pixels that aren't drawn transparent, the raster
+    // attributes set a 1:1 pixel aspect ratio and the image size
+    buf.push_str(&format!("\x1bP0;1;0q\"1;1;{w};{h}"));
+    for (i, c) in quant.color_map_rgb().chunks_exact(3).enumerate() {
+        // sixel colors are given in percent
+        let pct = |v: u8| (v as u32 * 100 + 127) / 255;
+        buf.push_str(&format!("#{i};2;{};{};{}", pct(c[0]), pct(c[1]), pct(c[2])));
+    }
+
+    let mut row = vec![0u8; w as usize];
+    for band in (0..h).step_by(6) {
+        let band_h = (h - band).min(6);
+        let mut used = [false; PALETTE_SIZE];
+        for y in band..band + band_h {
+            for x in 0..w {
+                used[indices[(y * w + x) as usize] as usize] = true;
+            }
+        }
+
+        let mut first = true;
+        for color in (0..PALETTE_SIZE).filter(|&c| used[c]) {
+            for (x, bits) in row.iter_mut().enumerate() {
+                *bits = 0;
+                for dy in 0..band_h {
+                    if indices[((band + dy) * w) as usize + x] as usize == color {
+                        *bits |= 1 << dy;
+                    }
+                }
+            }
+            // go back to the start of the band for every color after the first
+            if !first {
+                buf.push('$');
+            }
+            first = false;
+            buf.push_str(&format!("#{color}"));
+            push_run_length(&mut buf, &row);
+        }
+        buf.push('-');
+    }
+    buf.push_str("\x1b\\");
+
+    out.write_all(buf.as_bytes())?;
+    out.flush()
+}
+
+// every sixel is one character, repeats of 4 or more are shortened to !<count><char>
+fn push_run_length(buf: &mut String, row: &[u8]) {
+    let mut i = 0;
+    while i < row.len() {
+        let bits = row[i];
+        let run = row[i..].iter().take_while(|&&b| b == bits).count();
+        let c = (b'?' + bits) as char;
+        if run >= 4 {
+            buf.push_str(&format!("!{run}{c}"));
+        } else {
+            (0..run).for_each(|_| buf.push(c));
+        }
+        i += run;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::push_run_length;
+
+    #[test]
+    fn run_length_encoding() {
+        let mut buf = String::new();
+        push_run_length(&mut buf, &[0, 0, 63, 63, 63, 63, 63, 1]);
+        assert_eq!(buf, "??!5~@");
+    }
+}
@@ -0,0 +1,159 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum TileOrder {
+    /// left to right, top to bottom
+    Scanline,
+    /// outwards from the center of the image
+    Spiral,
+    /// along a hilbert curve, keeps consecutive tiles close together
+    Hilbert,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Splits a `width` x `height` image into tiles of at most `tile_size` pixels
+/// per side, returned in the order they should be rendered in.
+pub fn generate_tiles(width: u32, height: u32, tile_size: u32, order: TileOrder) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let cols = width.div_ceil(tile_size);
+    let rows = height.div_ceil(tile_size);
+
+    let cells = match order {
+        TileOrder::Scanline => (0..rows)
+            .flat_map(|y| (0..cols).map(move |x| (x, y)))
+            .collect(),
+        TileOrder::Spiral => spiral_cells(cols, rows),
+        TileOrder::Hilbert => hilbert_cells(cols, rows),
+    };
+
+    cells
+        .into_iter()
+        .map(|(cx, cy)| {
+            let x = cx * tile_size;
+            let y = cy * tile_size;
+            Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            }
+        })
+        .collect()
+}
+
+fn spiral_cells(cols: u32, rows: u32) -> Vec<(u32, u32)> {
+    let total = (cols * rows) as usize;
+    let mut cells = Vec::with_capacity(total);
+    let mut x = ((cols as i64) - 1) / 2;
+    let mut y = ((rows as i64) - 1) / 2;
+    let (mut dx, mut dy) = (1i64, 0i64);
+    let mut leg_len = 1;
+
+    // walk legs of growing length (1, 1, 2, 2, 3, 3, ...) and keep every cell
+    // that lands inside the grid until all of them have been visited
+    while cells.len() < total {
+        for _ in 0..2 {
+            for _ in 0..leg_len {
+                if (0..cols as i64).contains(&x) && (0..rows as i64).contains(&y) {
+                    cells.push((x as u32, y as u32));
+                }
+                x += dx;
+                y += dy;
+            }
+            (dx, dy) = (-dy, dx);
+        }
+        leg_len += 1;
+    }
+    cells
+}
+
+// the cells sorted by where the curve through the smallest square grid
+// around them passes them. the grid can be far bigger than the image for very
+// wide ones, so its cells aren't walked one by one
+fn hilbert_cells(cols: u32, rows: u32) -> Vec<(u32, u32)> {
+    let n = u64::from(cols.max(rows).max(1)).next_power_of_two();
+    let mut cells: Vec<(u32, u32)> = (0..rows)
+        .flat_map(|y| (0..cols).map(move |x| (x, y)))
+        .collect();
+    cells.sort_by_key(|&(x, y)| hilbert_xy2d(n, x.into(), y.into()));
+    cells
+}
+
+// converts a cell of an n x n grid to its distance along the hilbert curve
+// filling it
+fn hilbert_xy2d(n: u64, mut x: u64, mut y: u64) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod test {
+    use super::{generate_tiles, TileOrder};
+
+    #[test]
+    fn tiles_cover_image_once() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let (w, h) = (100, 70);
+            let mut covered = vec![0u32; (w * h) as usize];
+            for tile in generate_tiles(w, h, 16, order) {
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        covered[(y * w + x) as usize] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&c| c == 1), "{order:?}");
+        }
+    }
+
+    #[test]
+    fn spiral_starts_in_center() {
+        let tiles = generate_tiles(5 * 16, 3 * 16, 16, TileOrder::Spiral);
+        assert_eq!((tiles[0].x, tiles[0].y), (2 * 16, 16));
+    }
+
+    #[test]
+    fn hilbert_steps_to_neighbors() {
+        let tiles = generate_tiles(8 * 16, 8 * 16, 16, TileOrder::Hilbert);
+        assert_eq!(tiles.len(), 64);
+        for pair in tiles.windows(2) {
+            let step = pair[0].x.abs_diff(pair[1].x) + pair[0].y.abs_diff(pair[1].y);
+            assert_eq!(step, 16);
+        }
+    }
+
+    #[test]
+    fn hilbert_handles_very_wide_grids() {
+        // more than 65536 columns, the square grid around them has more
+        // cells than fit in a u32
+        let tiles = generate_tiles(70_000, 2, 1, TileOrder::Hilbert);
+        assert_eq!(tiles.len(), 140_000);
+        let mut seen = vec![false; 140_000];
+        for tile in tiles {
+            let i = (tile.y * 70_000 + tile.x) as usize;
+            assert!(!seen[i]);
+            seen[i] = true;
+        }
+    }
+}